@@ -3,5 +3,14 @@ use std::env;
 fn main() {
     env::set_var("SLINT_ENABLE_EXPERIMENTAL_FEATURES", "true");
 
+    // weather_provider is set when a provider fetching real weather data is built, the dummy
+    // controller is used otherwise (open_weather is not available on wasm)
+    println!("cargo:rustc-check-cfg=cfg(weather_provider)");
+    let open_weather = env::var_os("CARGO_FEATURE_OPEN_WEATHER").is_some()
+        && env::var("CARGO_CFG_TARGET_ARCH").as_deref() != Ok("wasm32");
+    if open_weather || env::var_os("CARGO_FEATURE_OPEN_METEO").is_some() {
+        println!("cargo:rustc-cfg=weather_provider");
+    }
+
     slint_build::compile("ui/main.slint").unwrap();
 }
@@ -0,0 +1,68 @@
+#!/usr/bin/env python3
+# Converts the GeoNames city extract to the compressed city list bundled with the application.
+#
+# Download and unpack cities15000.zip and admin1CodesASCII.txt from
+# https://download.geonames.org/export/dump/, then run:
+#
+#   python3 scripts/update_gazetteer.py cities15000.txt admin1CodesASCII.txt src/weather/gazetteer.tsv.gz
+#
+# The GeoNames data is licensed under CC BY 4.0, see https://www.geonames.org/.
+
+import gzip
+import sys
+
+HEADER = "# name\talternate names\tcountry\tadmin1\tlatitude\tlongitude\tpopulation\ttimezone"
+
+
+def read_admin1_names(path):
+    names = {}
+    with open(path, encoding="utf-8") as admin1_file:
+        for line in admin1_file:
+            code, name = line.rstrip("\n").split("\t")[:2]
+            names[code] = name
+    return names
+
+
+def read_cities(path, admin1_names):
+    cities = []
+    with open(path, encoding="utf-8") as cities_file:
+        for line in cities_file:
+            fields = line.rstrip("\n").split("\t")
+            name, alternate_names = fields[1], fields[3]
+            lat, lon, country, admin1 = fields[4], fields[5], fields[8], fields[10]
+            population, timezone = int(fields[14] or 0), fields[17]
+
+            # the fields are separated by tabs and the alternate names by commas
+            alternate_names = [
+                alternate_name
+                for alternate_name in dict.fromkeys(alternate_names.split(","))
+                if alternate_name and alternate_name != name and "\t" not in alternate_name
+            ]
+            state = admin1_names.get(f"{country}.{admin1}", "")
+            cities.append(
+                (population, [name, ",".join(alternate_names), country, state, lat, lon, str(population), timezone])
+            )
+
+    # the larger cities first, like in the bundled list
+    cities.sort(key=lambda city: -city[0])
+    return [fields for _, fields in cities]
+
+
+def main():
+    if len(sys.argv) != 4:
+        sys.exit(f"usage: {sys.argv[0]} cities15000.txt admin1CodesASCII.txt gazetteer.tsv.gz")
+    cities_path, admin1_path, output_path = sys.argv[1:]
+
+    cities = read_cities(cities_path, read_admin1_names(admin1_path))
+    lines = [HEADER] + ["\t".join(fields) for fields in cities]
+
+    # without a modification time the output only changes with the data
+    with open(output_path, "wb") as output_file:
+        with gzip.GzipFile(fileobj=output_file, mode="wb", mtime=0) as compressed:
+            compressed.write(("\n".join(lines) + "\n").encode("utf-8"))
+
+    print(f"{len(cities)} cities written to {output_path}")
+
+
+if __name__ == "__main__":
+    main()
@@ -1,63 +1,225 @@
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 
 use crate::ui::*;
 
+use crate::settings::Settings;
 use crate::weather;
-use weather::DummyWeatherController;
+use weather::gazetteer;
+use weather::locale::{set_current_locale, Locale};
+use weather::{DummyWeatherController, DEFAULT_CACHE_TTL_MINUTES};
 use weather::{WeatherControllerPointer, WeatherControllerSharedPointer, WeatherDisplayController};
 
+use slint::{ComponentHandle, Timer, TimerMode};
+
 #[cfg(all(not(target_arch = "wasm32"), feature = "open_weather"))]
 use weather::OpenWeatherController;
 
+#[cfg(feature = "open_meteo")]
+use weather::OpenMeteoController;
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "open_weather"))]
+use weather::runtime::spawn_task;
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "open_weather"))]
+const API_KEY_ENV_VARIABLE: &str = "RUSTY_WEATHER_API_KEY";
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "open_weather"))]
+const API_URL_ENV_VARIABLE: &str = "RUSTY_WEATHER_API_URL";
+
+pub type SettingsSharedPointer = Arc<Mutex<Settings>>;
+
+// Language of the translated UI strings, chosen at the start, unset without the gettext feature.
+static UI_LOCALE: std::sync::OnceLock<Locale> = std::sync::OnceLock::new();
+
+// The installed translations are looked up next to the executable and in the share directory of
+// its prefix, e.g. /usr/bin and /usr/share/locale, the ones of the sources are used otherwise.
+#[cfg(feature = "gettext")]
+fn translations_dir() -> std::path::PathBuf {
+    let installed = std::env::current_exe().ok().and_then(|exe| {
+        let exe_dir = exe.parent()?.to_path_buf();
+        [exe_dir.join("lang"), exe_dir.join("../share/locale")]
+            .into_iter()
+            .find(|dir| dir.join("de/LC_MESSAGES/rusty-weather.mo").exists())
+    });
+    installed.unwrap_or_else(|| concat!(env!("CARGO_MANIFEST_DIR"), "/lang/").into())
+}
+
+// the data age is updated and the outdated cities are refreshed on each tick
+const UPDATE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
 pub struct AppHandler {
     weather_controller: WeatherControllerSharedPointer,
     weather_display_controller: WeatherDisplayController,
+    settings: SettingsSharedPointer,
     window: Option<AppWindow>,
-    support_add_city: bool,
+    update_timer: Timer,
+    #[cfg_attr(
+        any(target_arch = "wasm32", not(feature = "open_weather")),
+        allow(dead_code)
+    )]
+    uses_api_key: bool,
+    #[cfg_attr(
+        any(target_arch = "wasm32", not(feature = "open_weather")),
+        allow(dead_code)
+    )]
+    uses_keyless_provider: bool,
+}
+
+// Weather provider chosen from the enabled features and the settings.
+pub struct SelectedController {
+    pub controller: WeatherControllerPointer,
+    pub uses_api_key: bool,
+    pub uses_keyless_provider: bool,
 }
 
 impl AppHandler {
     pub fn new() -> Self {
-        #[cfg_attr(
-            any(target_arch = "wasm32", not(feature = "open_weather")),
-            allow(unused_mut)
-        )]
-        let mut support_add_city = false;
+        let settings = Settings::load();
+        Self::apply_locale(settings.locale);
+        #[cfg(feature = "gettext")]
+        Self::init_translations();
+        gazetteer::load_bundled();
+
+        let SelectedController {
+            controller: data_controller,
+            uses_api_key,
+            uses_keyless_provider,
+        } = Self::select_controller(&settings);
+        let data_controller: WeatherControllerSharedPointer =
+            Arc::new(RwLock::new(data_controller));
+
+        let cache_ttl = chrono::Duration::minutes(
+            settings
+                .cache_ttl_minutes
+                .unwrap_or(DEFAULT_CACHE_TTL_MINUTES)
+                .max(1),
+        );
 
+        Self {
+            weather_controller: data_controller.clone(),
+            weather_display_controller: WeatherDisplayController::new(
+                &data_controller,
+                cache_ttl,
+                settings.offline_search,
+            ),
+            settings: Arc::new(Mutex::new(settings)),
+            window: None,
+            update_timer: Timer::default(),
+            uses_api_key,
+            uses_keyless_provider,
+        }
+    }
+
+    // OpenWeather when a key is available, then Open-Meteo, the dummy data otherwise.
+    #[cfg_attr(not(weather_provider), allow(unused_variables))]
+    pub fn select_controller(settings: &Settings) -> SelectedController {
         #[cfg_attr(
             any(target_arch = "wasm32", not(feature = "open_weather")),
             allow(unused_mut)
         )]
+        let mut uses_api_key = false;
+
+        #[cfg_attr(not(weather_provider), allow(unused_mut))]
         let mut data_controller_opt: Option<WeatherControllerPointer> = None;
 
+        #[cfg_attr(not(feature = "open_meteo"), allow(unused_mut))]
+        let mut uses_keyless_provider = false;
+
         #[cfg(all(not(target_arch = "wasm32"), feature = "open_weather"))]
         {
-            if let Some(api_key) = std::option_env!("RUSTY_WEATHER_API_KEY") {
-                data_controller_opt = Some(Box::new(OpenWeatherController::new(api_key.into())));
-                support_add_city = true;
+            if let Some(api_key) = Self::api_key(settings) {
+                data_controller_opt =
+                    Some(Arc::new(Self::open_weather_controller(api_key, settings)));
+                uses_api_key = true;
+            }
+        }
+
+        // Open-Meteo does not need an api key, it is used when OpenWeather is not configured
+        #[cfg(feature = "open_meteo")]
+        {
+            if data_controller_opt.is_none() {
+                log::info!("Weather API key not provided. Using Open-Meteo data.");
+                let mut open_meteo_controller = OpenMeteoController::new();
+                if let Some(limit) = settings.refresh_concurrency {
+                    open_meteo_controller.set_refresh_concurrency(limit);
+                }
+                data_controller_opt = Some(Arc::new(open_meteo_controller));
+                uses_keyless_provider = true;
             }
         }
 
-        let data_controller = match data_controller_opt {
+        let controller = match data_controller_opt {
             Some(data_contoller_some) => data_contoller_some,
             None => {
                 log::info!("Weather API key not provided. Using dummy data.");
-                Box::new(DummyWeatherController::new())
+                Arc::new(DummyWeatherController::new())
             }
         };
-        let data_controller: WeatherControllerSharedPointer = Arc::new(Mutex::new(data_controller));
 
-        Self {
-            weather_controller: data_controller.clone(),
-            weather_display_controller: WeatherDisplayController::new(&data_controller),
-            window: None,
-            support_add_city,
+        SelectedController {
+            controller,
+            uses_api_key,
+            uses_keyless_provider,
         }
     }
 
+    // The api key is taken from the settings file first, then from the environment.
+    #[cfg(all(not(target_arch = "wasm32"), feature = "open_weather"))]
+    fn api_key(settings: &Settings) -> Option<String> {
+        settings
+            .api_key
+            .clone()
+            .or_else(|| std::env::var(API_KEY_ENV_VARIABLE).ok())
+            .filter(|api_key| !api_key.is_empty())
+    }
+
+    // The api base url can be overridden, e.g. to use a proxy or a local mock server.
+    #[cfg(all(not(target_arch = "wasm32"), feature = "open_weather"))]
+    fn open_weather_controller(api_key: String, settings: &Settings) -> OpenWeatherController {
+        let mut controller = match std::env::var(API_URL_ENV_VARIABLE) {
+            Ok(base_url) if !base_url.is_empty() => {
+                log::info!("Using OpenWeather api url: {}", base_url);
+                OpenWeatherController::with_base_url(api_key, &base_url)
+            }
+            _ => OpenWeatherController::new(api_key),
+        };
+
+        if let Some(limit) = settings.refresh_concurrency {
+            controller.set_refresh_concurrency(limit);
+        }
+        controller
+    }
+
+    // Changes the language of the day names, the weather descriptions and the exports.
+    pub fn apply_locale(locale: Option<Locale>) -> Locale {
+        let locale = locale.unwrap_or_else(Locale::system);
+        log::debug!("Using locale: {}", locale.code());
+        set_current_locale(locale);
+        locale
+    }
+
+    // True when the UI strings are shown in another language than the chosen one.
+    fn restart_needed(locale: Locale) -> bool {
+        UI_LOCALE
+            .get()
+            .is_some_and(|ui_locale| *ui_locale != locale)
+    }
+
+    // gettext reads the language from the environment, which can only be changed safely before
+    // any other thread is started, so the UI strings keep the language until the next start.
+    #[cfg(feature = "gettext")]
+    fn init_translations() {
+        UI_LOCALE.get_or_init(|| {
+            let locale = weather::locale::current_locale();
+            std::env::set_var("LANGUAGE", locale.code());
+            slint::init_translations!(translations_dir());
+            locale
+        });
+    }
+
     pub fn save(&self) {
         log::debug!("Saving state");
-        if let Err(e) = self.weather_controller.lock().unwrap().save() {
+        if let Err(e) = self.weather_controller.read().unwrap().save() {
             log::warn!("Error while saving state: {}", e)
         }
     }
@@ -66,7 +228,7 @@ impl AppHandler {
     pub fn reload(&self) {
         log::debug!("Reloading state");
         if let Some(window) = &self.window {
-            self.weather_display_controller.refresh(window); // load new weather data
+            self.weather_display_controller.refresh_outdated(window); // load new weather data if needed
         } else {
             log::warn!("Cannot reload state, window not available.");
         }
@@ -74,12 +236,158 @@ impl AppHandler {
 
     pub fn initialize_ui(&mut self) {
         let window = AppWindow::new().expect("Cannot create main window!");
-        self.weather_display_controller
-            .initialize_ui(&window, self.support_add_city);
+        self.weather_display_controller.initialize_ui(&window);
+        self.initialize_units_ui(&window);
+        self.initialize_language_ui(&window);
+        self.initialize_update_timer(&window);
+
+        #[cfg(all(not(target_arch = "wasm32"), feature = "open_weather"))]
+        self.initialize_api_key_ui(&window);
 
         self.window = Some(window);
     }
 
+    fn initialize_update_timer(&self, window: &AppWindow) {
+        WeatherDisplayController::update_current_minute(window);
+
+        self.update_timer
+            .start(TimerMode::Repeated, UPDATE_INTERVAL, {
+                let window_weak = window.as_weak();
+                let display_controller = self.weather_display_controller.clone();
+                move || {
+                    if let Some(window) = window_weak.upgrade() {
+                        WeatherDisplayController::update_current_minute(&window);
+                        display_controller.refresh_outdated(&window);
+                    }
+                }
+            });
+    }
+
+    fn initialize_units_ui(&self, window: &AppWindow) {
+        let units = self.settings.lock().unwrap().units;
+        WeatherDisplayController::set_units(window, &units);
+
+        window.global::<UnitSettings>().on_units_changed({
+            let window_weak = window.as_weak();
+            let display_controller = self.weather_display_controller.clone();
+            let settings = self.settings.clone();
+
+            move || {
+                let window = window_weak.upgrade().unwrap();
+                let units = WeatherDisplayController::units_from_ui(&window);
+                WeatherDisplayController::set_units(&window, &units);
+
+                let mut settings = settings.lock().unwrap();
+                settings.units = units;
+                if let Err(e) = settings.save() {
+                    log::warn!("Error while saving settings: {}", e)
+                }
+
+                display_controller.redisplay(&window);
+            }
+        });
+    }
+
+    fn initialize_language_ui(&self, window: &AppWindow) {
+        // without the gettext feature the UI strings stay in English, choosing a language changes
+        // only the day names, the weather descriptions and the exports
+        WeatherDisplayController::set_locale(window, self.settings.lock().unwrap().locale);
+
+        window.global::<LanguageSettings>().on_language_changed({
+            let window_weak = window.as_weak();
+            let display_controller = self.weather_display_controller.clone();
+            let settings = self.settings.clone();
+
+            move || {
+                let window = window_weak.upgrade().unwrap();
+                let locale = WeatherDisplayController::locale_from_ui(&window);
+                let applied = Self::apply_locale(locale);
+                window
+                    .global::<LanguageSettings>()
+                    .set_restart_needed(Self::restart_needed(applied));
+
+                let mut settings = settings.lock().unwrap();
+                settings.locale = locale;
+                if let Err(e) = settings.save() {
+                    log::warn!("Error while saving settings: {}", e)
+                }
+
+                // fetch again to get the weather descriptions in the new language
+                display_controller.refresh(&window);
+            }
+        });
+    }
+
+    #[cfg(all(not(target_arch = "wasm32"), feature = "open_weather"))]
+    fn initialize_api_key_ui(&self, window: &AppWindow) {
+        let api_key_settings = window.global::<ApiKeySettings>();
+        api_key_settings.set_can_change_api_key(true);
+        api_key_settings.set_has_api_key(self.uses_api_key);
+        api_key_settings.set_uses_keyless_provider(self.uses_keyless_provider);
+
+        api_key_settings.on_apply_api_key({
+            let window_weak = window.as_weak();
+            let data_controller = self.weather_controller.clone();
+            let display_controller = self.weather_display_controller.clone();
+            let settings = self.settings.clone();
+
+            move |api_key| {
+                let window_weak = window_weak.clone();
+                let data_controller = data_controller.clone();
+                let display_controller = display_controller.clone();
+                let settings = settings.clone();
+                let api_key = api_key.trim().to_string();
+
+                spawn_task(async move {
+                    let open_weather_controller =
+                        Self::open_weather_controller(api_key.clone(), &settings.lock().unwrap());
+                    let check_res = open_weather_controller.check_api_key().await;
+
+                    if let Err(e) = &check_res {
+                        log::warn!("Api key check failed: {}", e);
+                    } else {
+                        log::info!("Api key accepted. Using OpenWeather data.");
+
+                        // save the current cities before switching the controller
+                        let mut data_controller = data_controller.write().unwrap();
+                        if let Err(e) = data_controller.save() {
+                            log::warn!("Error while saving state: {}", e)
+                        }
+                        *data_controller = Arc::new(open_weather_controller);
+
+                        let mut settings = settings.lock().unwrap();
+                        settings.api_key = Some(api_key);
+                        if let Err(e) = settings.save() {
+                            log::warn!("Error while saving settings: {}", e)
+                        }
+                    }
+
+                    let error_message = check_res.err().map(|e| e.to_string());
+                    let update_res = window_weak.upgrade_in_event_loop(move |window| {
+                        let api_key_settings = window.global::<ApiKeySettings>();
+                        match error_message {
+                            Some(error_message) => {
+                                api_key_settings.set_error(error_message.into());
+                            }
+                            None => {
+                                api_key_settings.set_error("".into());
+                                api_key_settings.set_has_api_key(true);
+
+                                display_controller.load(&window);
+                            }
+                        }
+                        api_key_settings.set_checking(false);
+                        window.global::<BusyLayerController>().invoke_unset_busy();
+                    });
+
+                    if let Err(e) = update_res {
+                        log::error!("Error while updating UI: {}", e);
+                    }
+                });
+            }
+        });
+    }
+
     pub fn run(&self) -> Result<(), slint::PlatformError> {
         let window = self.window.as_ref().expect("Cannot access main window!");
         self.weather_display_controller.load(window);
@@ -0,0 +1,574 @@
+use chrono::Utc;
+use serde_json::json;
+
+use crate::app_main::AppHandler;
+use crate::settings::Settings;
+use crate::weather;
+use weather::citylist::{export_city_list, import_city_list, ImportMode};
+use weather::coordinates::{locate_coordinates, parse_coordinates};
+use weather::export::{export_weather, ExportFormat};
+use weather::gazetteer;
+use weather::runtime::block_on;
+use weather::units::Units;
+use weather::utils::get_day_name_from_timestamp;
+use weather::{CityData, CityWeatherData, LoadStatus, RefreshStatus, WeatherControllerPointer};
+
+const USAGE: &str = "Usage: rusty-weather [<command>] [--json] [--replace]
+
+Without a command, the application window is opened.
+
+Commands:
+  list                    list the saved cities with the cached weather
+  add <query>             add the best match for a city name or coordinates
+  remove <city>           remove a city
+  move <city> <position>  move a city to the position in the list
+  refresh                 fetch the weather for all the cities
+  show <city>             show the current weather and the forecast of a city
+  export <city> <format> [<file>]
+                          export the forecast of a city as csv, json or ics,
+                          to the file or to the standard output
+  cities export [<file>]  export the list of the cities, to the file or to the
+                          standard output
+  cities import <file>    add the cities of an exported list, or replace the
+                          list with them with --replace
+  help                    show this help
+
+A city is given by its position in the list or by its name.
+With --json, the weather is printed as JSON instead of a table.";
+
+type CliResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+struct Cli {
+    data_controller: WeatherControllerPointer,
+    settings: Settings,
+    json: bool,
+}
+
+// Runs the command given on the command line and returns the process exit code.
+pub fn run(args: &[String]) -> i32 {
+    let json = args.iter().any(|arg| arg == "--json");
+    let replace = args.iter().any(|arg| arg == "--replace");
+    let args: Vec<&str> = args
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| *arg != "--json" && *arg != "--replace")
+        .collect();
+
+    let Some((&command, command_args)) = args.split_first() else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+    if matches!(command, "help" | "--help" | "-h") {
+        println!("{}", USAGE);
+        return 0;
+    }
+
+    let settings = Settings::load();
+    AppHandler::apply_locale(settings.locale);
+    let cli = Cli {
+        data_controller: AppHandler::select_controller(&settings).controller,
+        settings,
+        json,
+    };
+
+    let res = match (command, command_args) {
+        ("list", []) => cli.list(),
+        ("add", [_, ..]) => cli.add(&command_args.join(" ")),
+        ("remove", [city]) => cli.remove(city),
+        ("move", [city, position]) => cli.move_city(city, position),
+        ("refresh", []) => cli.refresh(),
+        ("show", [city]) => cli.show(city),
+        ("export", [city, format]) => cli.export(city, format, None),
+        ("export", [city, format, path]) => cli.export(city, format, Some(path)),
+        ("cities", ["export"]) => cli.export_cities(None),
+        ("cities", ["export", path]) => cli.export_cities(Some(path)),
+        ("cities", ["import", path]) => cli.import_cities(path, replace),
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+
+    match res {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+fn invalid_input(message: String) -> Box<dyn std::error::Error + Send + Sync> {
+    Box::new(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        message,
+    ))
+}
+
+// The refresh fails when any city could not be updated, so scripts and cron jobs notice it.
+fn check_refreshed(cities: &[CityWeatherData]) -> CliResult {
+    let not_updated: Vec<&str> = cities
+        .iter()
+        .filter(|city| city.refresh_status != RefreshStatus::Fresh)
+        .map(|city| city.city_data.display_name())
+        .collect();
+    if not_updated.is_empty() {
+        return Ok(());
+    }
+
+    Err(Box::new(std::io::Error::other(format!(
+        "{} of {} cities not updated: {}",
+        not_updated.len(),
+        cities.len(),
+        not_updated.join(", ")
+    ))))
+}
+
+// Index of the city given by its 1-based position, its name or its label, case insensitive.
+fn find_city(
+    cities: &[CityWeatherData],
+    selector: &str,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    if let Ok(position) = selector.parse::<usize>() {
+        if (1..=cities.len()).contains(&position) {
+            return Ok(position - 1);
+        }
+        return Err(invalid_input(format!("No city at position {}", position)));
+    }
+
+    // also for the names which are not in ASCII, e.g. "москва"
+    let name = selector.trim().to_lowercase();
+    let matches: Vec<usize> = cities
+        .iter()
+        .enumerate()
+        .filter(|(_, city)| {
+            let city_data = &city.city_data;
+            city_data.city_name.to_lowercase() == name
+                || city_data
+                    .label
+                    .as_ref()
+                    .is_some_and(|label| label.to_lowercase() == name)
+        })
+        .map(|(index, _)| index)
+        .collect();
+
+    match matches[..] {
+        [index] => Ok(index),
+        [] => Err(invalid_input(format!("No city named {}", selector))),
+        _ => Err(invalid_input(format!(
+            "More than one city named {}, use the position instead",
+            selector
+        ))),
+    }
+}
+
+fn format_age(fetched_at: Option<i64>, now: i64) -> String {
+    let Some(fetched_at) = fetched_at else {
+        return "never".to_string();
+    };
+
+    let minutes = (now - fetched_at).max(0) / 60;
+    match minutes {
+        0 => "just now".to_string(),
+        1..=59 => format!("{} min ago", minutes),
+        60..=1439 => format!("{} h ago", minutes / 60),
+        _ => format!("{} d ago", minutes / 1440),
+    }
+}
+
+// Columns padded to the widest cell, the first row is the header.
+fn format_table(rows: &[Vec<String>]) -> String {
+    let column_count = rows.iter().map(Vec::len).max().unwrap_or_default();
+    let widths: Vec<usize> = (0..column_count)
+        .map(|column| {
+            rows.iter()
+                .filter_map(|row| row.get(column))
+                .map(|cell| cell.chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    rows.iter()
+        .map(|row| {
+            let line: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| {
+                    format!("{}{}", cell, " ".repeat(width - cell.chars().count()))
+                })
+                .collect();
+            line.join("  ").trim_end().to_string()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn format_temperature(celsius: f64, units: &Units) -> String {
+    format!(
+        "{:.0}{}",
+        units.temperature.convert(celsius),
+        units.temperature.label()
+    )
+}
+
+impl Cli {
+    fn load(&self) -> CliResult {
+        if self.data_controller.load()? == LoadStatus::Recovered {
+            eprintln!("The saved cities could not be read and were restored from a backup.");
+        }
+        Ok(())
+    }
+
+    fn cities(&self) -> Result<Vec<CityWeatherData>, Box<dyn std::error::Error + Send + Sync>> {
+        self.load()?;
+        self.data_controller.cached_cities()
+    }
+
+    fn print_cities(&self, cities: &[CityWeatherData]) -> CliResult {
+        if self.json {
+            let list: Vec<serde_json::Value> = cities
+                .iter()
+                .enumerate()
+                .map(|(index, city)| {
+                    let current_data = &city.weather_data.current_data;
+                    json!({
+                        "position": index + 1,
+                        "name": city.city_data.city_name,
+                        "label": city.city_data.label,
+                        "note": city.city_data.note,
+                        "lat": city.city_data.lat,
+                        "lon": city.city_data.lon,
+                        "temperature": current_data.current_temperature,
+                        "description": current_data.description,
+                        "fetched_at": city.fetched_at,
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&list)?);
+            return Ok(());
+        }
+
+        let now = Utc::now().timestamp();
+        let mut rows = vec![vec![
+            "#".to_string(),
+            "City".to_string(),
+            "Temp".to_string(),
+            "Weather".to_string(),
+            "Updated".to_string(),
+        ]];
+        for (index, city) in cities.iter().enumerate() {
+            let current_data = &city.weather_data.current_data;
+            let updated = match &city.refresh_status {
+                RefreshStatus::Fresh => format_age(city.fetched_at, now),
+                RefreshStatus::Stale(reason) | RefreshStatus::Failed(reason) => {
+                    format!(
+                        "{}, not updated: {}",
+                        format_age(city.fetched_at, now),
+                        reason
+                    )
+                }
+            };
+            rows.push(vec![
+                (index + 1).to_string(),
+                city.city_data.display_name().to_string(),
+                format_temperature(current_data.current_temperature, &self.settings.units),
+                current_data.description.clone(),
+                updated,
+            ]);
+        }
+        println!("{}", format_table(&rows));
+        Ok(())
+    }
+
+    fn list(&self) -> CliResult {
+        self.print_cities(&self.cities()?)
+    }
+
+    fn add(&self, query: &str) -> CliResult {
+        self.load()?;
+
+        let location = match parse_coordinates(query) {
+            Some((lat, lon)) => block_on(locate_coordinates(&self.data_controller, lat, lon)),
+            None => {
+                let locations = if self.settings.offline_search {
+                    gazetteer::search_location(query)
+                } else {
+                    block_on(self.data_controller.search_location(query.to_string()))?
+                };
+                locations
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| invalid_input(format!("No location found for {}", query)))?
+            }
+        };
+
+        let city = CityData {
+            lat: location.lat,
+            lon: location.lon,
+            city_name: location.name,
+            label: None,
+            note: None,
+        };
+        match block_on(self.data_controller.add_city(city.clone()))? {
+            Some(city_weather) => {
+                self.data_controller.save()?;
+                if self.json {
+                    println!("{}", serde_json::to_string_pretty(&city_weather)?);
+                } else {
+                    println!(
+                        "Added {} ({:.4}, {:.4})",
+                        city.city_name, city.lat, city.lon
+                    );
+                }
+                Ok(())
+            }
+            None => Err(invalid_input(format!(
+                "{} is already in the list",
+                city.city_name
+            ))),
+        }
+    }
+
+    fn remove(&self, selector: &str) -> CliResult {
+        let cities = self.cities()?;
+        let index = find_city(&cities, selector)?;
+
+        self.data_controller.remove_city(index)?;
+        self.data_controller.save()?;
+        println!("Removed {}", cities[index].city_data.display_name());
+        Ok(())
+    }
+
+    fn move_city(&self, selector: &str, position: &str) -> CliResult {
+        let cities = self.cities()?;
+        let index = find_city(&cities, selector)?;
+        let new_index = position
+            .parse::<usize>()
+            .ok()
+            .filter(|position| (1..=cities.len()).contains(position))
+            .map(|position| position - 1)
+            .ok_or_else(|| invalid_input(format!("Invalid position {}", position)))?;
+
+        self.data_controller.reorder_cities(index, new_index)?;
+        self.data_controller.save()?;
+        println!(
+            "Moved {} to position {}",
+            cities[index].city_data.display_name(),
+            new_index + 1
+        );
+        Ok(())
+    }
+
+    fn refresh(&self) -> CliResult {
+        self.load()?;
+        let cities = block_on(self.data_controller.refresh_cities())?;
+        self.data_controller.save()?;
+        self.print_cities(&cities)?;
+        check_refreshed(&cities)
+    }
+
+    fn show(&self, selector: &str) -> CliResult {
+        let cities = self.cities()?;
+        let city = &cities[find_city(&cities, selector)?];
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(city)?);
+            return Ok(());
+        }
+
+        let units = &self.settings.units;
+        let current_data = &city.weather_data.current_data;
+        let city_data = &city.city_data;
+        let name = match &city_data.label {
+            Some(label) => format!("{} - {}", label, city_data.city_name),
+            None => city_data.city_name.clone(),
+        };
+        println!(
+            "{} ({:.4}, {:.4}), updated {}",
+            name,
+            city_data.lat,
+            city_data.lon,
+            format_age(city.fetched_at, Utc::now().timestamp())
+        );
+        if let Some(note) = &city_data.note {
+            println!("{}", note);
+        }
+        println!(
+            "{}, {}, feels like {}, humidity {:.0}%, wind {:.0} {}",
+            format_temperature(current_data.current_temperature, units),
+            current_data.description,
+            format_temperature(current_data.feels_like_temperature, units),
+            current_data.humidity,
+            units.wind_speed.convert(current_data.wind_speed),
+            units.wind_speed.label()
+        );
+        for alert in &city.alerts {
+            println!("Alert: {} ({})", alert.event, alert.sender);
+        }
+        println!();
+
+        let mut rows = vec![vec![
+            "Day".to_string(),
+            "Weather".to_string(),
+            "Min".to_string(),
+            "Max".to_string(),
+            "Rain".to_string(),
+            "Amount".to_string(),
+        ]];
+        for forecast in &city.weather_data.forecast_data {
+            let weather_data = &forecast.weather_data;
+            let precipitation = &weather_data.precipitation;
+            rows.push(vec![
+                get_day_name_from_timestamp(forecast.timestamp, city.weather_data.utc_offset),
+                weather_data.description.clone(),
+                format_temperature(weather_data.detailed_temperature.min, units),
+                format_temperature(weather_data.detailed_temperature.max, units),
+                format!("{:.0}%", precipitation.probability * 100.0),
+                format!(
+                    "{:.1} {}",
+                    units
+                        .precipitation
+                        .convert(precipitation.rain_volume + precipitation.snow_volume),
+                    units.precipitation.label()
+                ),
+            ]);
+        }
+        println!("{}", format_table(&rows));
+        Ok(())
+    }
+
+    fn export(&self, selector: &str, format: &str, path: Option<&str>) -> CliResult {
+        let format = ExportFormat::from_name(format)
+            .ok_or_else(|| invalid_input(format!("Unknown export format {}", format)))?;
+        let cities = self.cities()?;
+        let city = &cities[find_city(&cities, selector)?];
+
+        let data = export_weather(city, format, &self.settings.units)?;
+        match path {
+            Some(path) => std::fs::write(path, data)?,
+            None => print!("{}", data),
+        }
+        Ok(())
+    }
+
+    fn export_cities(&self, path: Option<&str>) -> CliResult {
+        self.load()?;
+        let data = export_city_list(&self.data_controller)?;
+        match path {
+            Some(path) => std::fs::write(path, data)?,
+            None => println!("{}", data),
+        }
+        Ok(())
+    }
+
+    // The new cities are added without the weather, it is fetched by the next refresh.
+    fn import_cities(&self, path: &str, replace: bool) -> CliResult {
+        let mode = match replace {
+            true => ImportMode::Replace,
+            false => ImportMode::Merge,
+        };
+
+        self.load()?;
+        let summary =
+            import_city_list(&self.data_controller, &std::fs::read_to_string(path)?, mode)?;
+        self.data_controller.save()?;
+
+        println!(
+            "Added {} cities, skipped {} already in the list, removed {}",
+            summary.added, summary.duplicates, summary.removed
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn city(name: &str) -> CityWeatherData {
+        CityWeatherData {
+            city_data: CityData {
+                lat: 0.0,
+                lon: 0.0,
+                city_name: name.to_string(),
+                label: None,
+                note: None,
+            },
+            weather_data: Default::default(),
+            alerts: vec![],
+            refresh_status: RefreshStatus::Fresh,
+            fetched_at: None,
+            observed_at: None,
+        }
+    }
+
+    #[test]
+    fn cities_are_found_by_position_or_name() {
+        let cities = vec![
+            city("Berlin"),
+            city("Vienna"),
+            city("Berlin"),
+            city("München"),
+            city("Москва"),
+        ];
+
+        assert_eq!(find_city(&cities, "2").unwrap(), 1);
+        assert_eq!(find_city(&cities, "vienna").unwrap(), 1);
+        assert_eq!(find_city(&cities, "MÜNCHEN").unwrap(), 3);
+        assert_eq!(find_city(&cities, "москва").unwrap(), 4);
+        assert!(find_city(&cities, "6").is_err());
+        assert!(find_city(&cities, "Munich").is_err());
+        // ambiguous name
+        assert!(find_city(&cities, "Berlin").is_err());
+    }
+
+    #[cfg(feature = "open_meteo")]
+    #[test]
+    fn cities_are_listed_before_the_first_save() {
+        let mut controller = weather::OpenMeteoController::new();
+        controller.set_state_store(std::sync::Arc::new(weather::MemoryStateStore::default()));
+        let cli = Cli {
+            data_controller: std::sync::Arc::new(controller),
+            settings: Settings::default(),
+            json: false,
+        };
+
+        assert!(cli.list().is_ok());
+        assert!(cli.cities().unwrap().is_empty());
+    }
+
+    #[test]
+    fn refresh_fails_when_a_city_is_not_updated() {
+        let mut cities = vec![city("Berlin"), city("Vienna"), city("Oslo")];
+        assert!(check_refreshed(&cities).is_ok());
+
+        cities[1].refresh_status = RefreshStatus::Stale("timeout".to_string());
+        cities[2].refresh_status = RefreshStatus::Failed("not found".to_string());
+        let error = check_refreshed(&cities).unwrap_err();
+        assert_eq!(error.to_string(), "2 of 3 cities not updated: Vienna, Oslo");
+    }
+
+    #[test]
+    fn table_columns_are_aligned() {
+        let rows = vec![
+            vec!["#".to_string(), "City".to_string(), "Temp".to_string()],
+            vec!["1".to_string(), "Zürich".to_string(), "4°C".to_string()],
+            vec!["2".to_string(), "Rio".to_string(), "27°C".to_string()],
+        ];
+        assert_eq!(
+            format_table(&rows),
+            "#  City    Temp\n1  Zürich  4°C\n2  Rio     27°C"
+        );
+    }
+
+    #[test]
+    fn data_age_is_formatted() {
+        let now = 1_700_000_000;
+        assert_eq!(format_age(None, now), "never");
+        assert_eq!(format_age(Some(now - 30), now), "just now");
+        assert_eq!(format_age(Some(now - 5 * 60), now), "5 min ago");
+        assert_eq!(format_age(Some(now - 3 * 3600), now), "3 h ago");
+        assert_eq!(format_age(Some(now - 2 * 86400), now), "2 d ago");
+    }
+}
@@ -5,6 +5,7 @@ pub mod ui {
 }
 
 mod app_main;
+mod settings;
 mod weather;
 
 use crate::app_main::AppHandler;
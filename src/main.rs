@@ -7,17 +7,28 @@ pub mod ui {
 }
 
 mod app_main;
+mod cli;
+mod settings;
 mod weather;
 
 fn main() -> Result<(), slint::PlatformError> {
+    // a command runs without the window, only the warnings are logged to keep the output readable
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
     env_logger::Builder::default()
-        .filter_level(if cfg!(debug_assertions) {
+        .filter_level(if !args.is_empty() {
+            log::LevelFilter::Warn
+        } else if cfg!(debug_assertions) {
             log::LevelFilter::Debug
         } else {
             log::LevelFilter::Info
         })
         .init();
 
+    if !args.is_empty() {
+        std::process::exit(cli::run(&args));
+    }
+
     let mut app_handler = AppHandler::new();
     app_handler.initialize_ui();
 
@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::weather::locale::Locale;
+use crate::weather::units::Units;
+use crate::weather::utils::project_data_dir;
+
+const SETTINGS_STORED_FILE_NAME: &str = "settings.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Settings {
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub units: Units,
+    // None means the system locale
+    #[serde(default)]
+    pub locale: Option<Locale>,
+    // number of cities updated at the same time, None means the default limit
+    #[serde(default)]
+    pub refresh_concurrency: Option<usize>,
+    // minutes after which the cached weather is fetched again, None means the default
+    #[serde(default)]
+    pub cache_ttl_minutes: Option<i64>,
+    // search the locations only in the bundled city list, without the provider's geocoding api
+    #[serde(default)]
+    pub offline_search: bool,
+}
+
+impl Settings {
+    fn storage_path() -> Option<PathBuf> {
+        project_data_dir().map(|project_dir| project_dir.join(SETTINGS_STORED_FILE_NAME))
+    }
+
+    pub fn load() -> Self {
+        match Self::read() {
+            Ok(settings) => settings,
+            Err(e) => {
+                log::debug!("Settings not loaded, using defaults: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    fn read() -> Result<Self, Box<dyn std::error::Error>> {
+        let storage_path = Self::storage_path().ok_or(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Storage path not initialized",
+        ))?;
+        log::debug!("Loading settings from: {:?}", storage_path.to_str());
+
+        let file = File::open(storage_path.as_path())?;
+        let reader = BufReader::new(file);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let storage_path = Self::storage_path().ok_or(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Storage path not initialized",
+        ))?;
+        log::debug!("Saving settings to: {:?}", storage_path.to_str());
+
+        // Ensure the parent directories exist
+        if let Some(parent_dir) = storage_path.parent() {
+            std::fs::create_dir_all(parent_dir)?;
+        }
+
+        let file = File::create(storage_path)?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+use jni::objects::{JObject, JValue};
+use jni::JNIEnv;
+use std::io;
+
+// Files are shared through the Download directory of the device, visible in the Files app.
+const DOWNLOADS_COLLECTION: &str = "android/provider/MediaStore$Downloads";
+const DOWNLOAD_DIR: &str = "Download";
+
+// Needed to read the files copied to the device, the files saved by the application can be read
+// without it.
+const READ_PERMISSION: &str = "android.permission.READ_EXTERNAL_STORAGE";
+
+// Local references freed after each call, the event loop never returns to Java to free them.
+const LOCAL_FRAME_CAPACITY: i32 = 32;
+
+type StorageResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// Runs f with the activity of the application, a pending Java exception is logged and cleared.
+fn with_activity<T>(f: impl FnOnce(&mut JNIEnv, &JObject) -> StorageResult<T>) -> StorageResult<T> {
+    let context = ndk_context::android_context();
+    let vm = unsafe { jni::JavaVM::from_raw(context.vm().cast()) }?;
+    let mut env = vm.attach_current_thread()?;
+    let activity = unsafe { JObject::from_raw(context.context().cast()) };
+
+    let result = env.with_local_frame(LOCAL_FRAME_CAPACITY, |env| f(env, &activity));
+    if env.exception_check()? {
+        env.exception_describe()?;
+        env.exception_clear()?;
+    }
+    result
+}
+
+fn downloads_uri<'local>(env: &mut JNIEnv<'local>) -> StorageResult<JObject<'local>> {
+    Ok(env
+        .get_static_field(
+            DOWNLOADS_COLLECTION,
+            "EXTERNAL_CONTENT_URI",
+            "Landroid/net/Uri;",
+        )?
+        .l()?)
+}
+
+fn content_resolver<'local>(
+    env: &mut JNIEnv<'local>,
+    activity: &JObject,
+) -> StorageResult<JObject<'local>> {
+    Ok(env
+        .call_method(
+            activity,
+            "getContentResolver",
+            "()Landroid/content/ContentResolver;",
+            &[],
+        )?
+        .l()?)
+}
+
+// E.g. "Download/cities.json".
+pub fn download_location(file_name: &str) -> String {
+    format!("{}/{}", DOWNLOAD_DIR, file_name)
+}
+
+fn not_found(message: String) -> Box<dyn std::error::Error + Send + Sync> {
+    Box::new(io::Error::new(io::ErrorKind::NotFound, message))
+}
+
+// Saves the file in the Download directory with the media store and returns its location.
+pub fn save_download(file_name: &str, mime_type: &str, data: &str) -> StorageResult<String> {
+    with_activity(|env, activity| {
+        let values = env.new_object("android/content/ContentValues", "()V", &[])?;
+        for (column, value) in [
+            ("_display_name", file_name),
+            ("mime_type", mime_type),
+            ("relative_path", DOWNLOAD_DIR),
+        ] {
+            let column = env.new_string(column)?;
+            let value = env.new_string(value)?;
+            env.call_method(
+                &values,
+                "put",
+                "(Ljava/lang/String;Ljava/lang/String;)V",
+                &[JValue::Object(&column), JValue::Object(&value)],
+            )?;
+        }
+
+        let resolver = content_resolver(env, activity)?;
+        let collection = downloads_uri(env)?;
+        let uri = env
+            .call_method(
+                &resolver,
+                "insert",
+                "(Landroid/net/Uri;Landroid/content/ContentValues;)Landroid/net/Uri;",
+                &[JValue::Object(&collection), JValue::Object(&values)],
+            )?
+            .l()?;
+        if uri.is_null() {
+            return Err(not_found(format!("Cannot create {}", file_name)));
+        }
+
+        let stream = env
+            .call_method(
+                &resolver,
+                "openOutputStream",
+                "(Landroid/net/Uri;)Ljava/io/OutputStream;",
+                &[JValue::Object(&uri)],
+            )?
+            .l()?;
+        let bytes = env.byte_array_from_slice(data.as_bytes())?;
+        env.call_method(&stream, "write", "([B)V", &[JValue::Object(&bytes)])?;
+        env.call_method(&stream, "close", "()V", &[])?;
+
+        // the media store adds a number to the name when the file exists already
+        Ok(download_location(file_name))
+    })
+}
+
+// Asks for the permission to read the files of the other applications, the result is used by
+// the next read.
+fn request_read_permission(env: &mut JNIEnv, activity: &JObject) -> StorageResult<()> {
+    let permission = env.new_string(READ_PERMISSION)?;
+    let granted = env
+        .call_method(
+            activity,
+            "checkSelfPermission",
+            "(Ljava/lang/String;)I",
+            &[JValue::Object(&permission)],
+        )?
+        .i()?
+        == 0;
+    if !granted {
+        let permissions = env.new_object_array(1, "java/lang/String", &permission)?;
+        env.call_method(
+            activity,
+            "requestPermissions",
+            "([Ljava/lang/String;I)V",
+            &[JValue::Object(&permissions), JValue::Int(0)],
+        )?;
+    }
+    Ok(())
+}
+
+// Reads the newest file in the Download directory saved under the name, also when the media store
+// numbered it, e.g. "cities (1).json" saved next to an older "cities.json".
+pub fn read_download(file_name: &str) -> StorageResult<String> {
+    let (stem, extension) = file_name.rsplit_once('.').unwrap_or((file_name, ""));
+    with_activity(|env, activity| {
+        if let Err(e) = request_read_permission(env, activity) {
+            log::warn!("Failed to request the storage permission: {}", e);
+            env.exception_clear()?;
+        }
+
+        let resolver = content_resolver(env, activity)?;
+        let collection = downloads_uri(env)?;
+        let id_column = env.new_string("_id")?;
+        let projection = env.new_object_array(1, "java/lang/String", &id_column)?;
+        let selection = env.new_string("_display_name LIKE ?")?;
+        let name_pattern = env.new_string(format!("{}%.{}", stem, extension))?;
+        let selection_args = env.new_object_array(1, "java/lang/String", &name_pattern)?;
+        let sort_order = env.new_string("date_modified DESC")?;
+
+        let cursor = env
+            .call_method(
+                &resolver,
+                "query",
+                "(Landroid/net/Uri;[Ljava/lang/String;Ljava/lang/String;[Ljava/lang/String;Ljava/lang/String;)Landroid/database/Cursor;",
+                &[
+                    JValue::Object(&collection),
+                    JValue::Object(&projection),
+                    JValue::Object(&selection),
+                    JValue::Object(&selection_args),
+                    JValue::Object(&sort_order),
+                ],
+            )?
+            .l()?;
+        if cursor.is_null() {
+            return Err(not_found(format!("Cannot search {}", DOWNLOAD_DIR)));
+        }
+        let found = env.call_method(&cursor, "moveToFirst", "()Z", &[])?.z()?;
+        let id = match found {
+            true => Some(
+                env.call_method(&cursor, "getLong", "(I)J", &[JValue::Int(0)])?
+                    .j()?,
+            ),
+            false => None,
+        };
+        env.call_method(&cursor, "close", "()V", &[])?;
+        let Some(id) = id else {
+            return Err(not_found(format!(
+                "No {} found",
+                download_location(file_name)
+            )));
+        };
+
+        let uri = env
+            .call_static_method(
+                "android/content/ContentUris",
+                "withAppendedId",
+                "(Landroid/net/Uri;J)Landroid/net/Uri;",
+                &[JValue::Object(&collection), JValue::Long(id)],
+            )?
+            .l()?;
+        let stream = env
+            .call_method(
+                &resolver,
+                "openInputStream",
+                "(Landroid/net/Uri;)Ljava/io/InputStream;",
+                &[JValue::Object(&uri)],
+            )?
+            .l()?;
+
+        let buffer = env.new_byte_array(8192)?;
+        let mut chunk = vec![0i8; 8192];
+        let mut data = Vec::new();
+        loop {
+            let count = env
+                .call_method(&stream, "read", "([B)I", &[JValue::Object(&buffer)])?
+                .i()?;
+            if count < 0 {
+                break;
+            }
+            env.get_byte_array_region(&buffer, 0, &mut chunk[..count as usize])?;
+            data.extend(chunk[..count as usize].iter().map(|byte| *byte as u8));
+        }
+        env.call_method(&stream, "close", "()V", &[])?;
+
+        Ok(String::from_utf8(data)?)
+    })
+}
@@ -0,0 +1,319 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+
+#[cfg(target_os = "android")]
+use crate::weather::androidstorage;
+use crate::weather::coordinates::format_coordinates;
+use crate::weather::export::save_file;
+#[cfg(all(not(target_arch = "wasm32"), not(target_os = "android")))]
+use crate::weather::utils::export_dir;
+use crate::weather::weathercontroller::{CityData, WeatherControllerPointer};
+
+// Portable list of the saved cities, without the weather, to set up the same cities elsewhere.
+const CITY_LIST_FORMAT: &str = "rusty-weather-cities";
+
+// Version of the city list file, increased with each incompatible change.
+const CITY_LIST_VERSION: u64 = 1;
+
+// Name of the file the list is exported to by the application.
+pub const CITY_LIST_FILE_NAME: &str = "rusty-weather-cities.json";
+
+#[derive(Serialize, Deserialize)]
+struct CityListFile {
+    format: String,
+    version: u64,
+    // in the order of the list
+    cities: Vec<CityListEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CityListEntry {
+    name: String,
+    lat: f64,
+    lon: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImportMode {
+    // the new cities are added after the current ones
+    Merge,
+    // the current cities are replaced by the imported ones
+    Replace,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct ImportSummary {
+    pub added: usize,
+    // the imported cities skipped as they are already in the list
+    pub duplicates: usize,
+    pub removed: usize,
+}
+
+fn invalid_data(message: String) -> Box<dyn std::error::Error + Send + Sync> {
+    Box::new(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+pub fn city_list_to_json(
+    cities: &[CityData],
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let file = CityListFile {
+        format: CITY_LIST_FORMAT.to_string(),
+        version: CITY_LIST_VERSION,
+        cities: cities
+            .iter()
+            .map(|city| CityListEntry {
+                name: city.city_name.clone(),
+                lat: city.lat,
+                lon: city.lon,
+                label: city.label.clone(),
+                note: city.note.clone(),
+            })
+            .collect(),
+    };
+    Ok(serde_json::to_string_pretty(&file)?)
+}
+
+pub fn city_list_from_json(
+    data: &str,
+) -> Result<Vec<CityData>, Box<dyn std::error::Error + Send + Sync>> {
+    let file: CityListFile = serde_json::from_str(data)?;
+    if file.format != CITY_LIST_FORMAT {
+        return Err(invalid_data(format!("Not a city list: {}", file.format)));
+    }
+    if file.version > CITY_LIST_VERSION {
+        return Err(invalid_data(format!(
+            "The city list was written by a newer version of the application (version {})",
+            file.version
+        )));
+    }
+
+    file.cities
+        .into_iter()
+        .map(|entry| {
+            if !(-90.0..=90.0).contains(&entry.lat) || !(-180.0..=180.0).contains(&entry.lon) {
+                return Err(invalid_data(format!(
+                    "Invalid coordinates of {}: {}, {}",
+                    entry.name, entry.lat, entry.lon
+                )));
+            }
+
+            let name = entry.name.trim();
+            let mut city = CityData {
+                city_name: match name {
+                    "" => format_coordinates(entry.lat, entry.lon),
+                    _ => name.to_string(),
+                },
+                lat: entry.lat,
+                lon: entry.lon,
+                label: None,
+                note: None,
+            };
+            city.annotate(entry.label, entry.note);
+            Ok(city)
+        })
+        .collect()
+}
+
+// The cities are compared by their coordinates only. The duplicates are dropped,
+// also the repeated cities of the imported list.
+pub fn merge_city_lists(
+    current: &[CityData],
+    imported: Vec<CityData>,
+    mode: ImportMode,
+) -> (Vec<CityData>, ImportSummary) {
+    let mut summary = ImportSummary::default();
+    let mut cities = match mode {
+        ImportMode::Merge => current.to_vec(),
+        ImportMode::Replace => vec![],
+    };
+
+    for city in imported {
+        if cities.iter().any(|other| other.is_same_place(&city)) {
+            summary.duplicates += 1;
+            continue;
+        }
+        if !current.iter().any(|other| other.is_same_place(&city)) {
+            summary.added += 1;
+        }
+        cities.push(city);
+    }
+
+    summary.removed = current
+        .iter()
+        .filter(|city| !cities.iter().any(|other| other.is_same_place(city)))
+        .count();
+
+    (cities, summary)
+}
+
+pub fn export_city_list(
+    data_controller: &WeatherControllerPointer,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let cities: Vec<CityData> = data_controller
+        .cached_cities()?
+        .into_iter()
+        .map(|city| city.city_data)
+        .collect();
+    city_list_to_json(&cities)
+}
+
+// The cities are only changed in the controller, the caller saves them.
+pub fn import_city_list(
+    data_controller: &WeatherControllerPointer,
+    data: &str,
+    mode: ImportMode,
+) -> Result<ImportSummary, Box<dyn std::error::Error + Send + Sync>> {
+    let imported = city_list_from_json(data)?;
+    let current: Vec<CityData> = data_controller
+        .cached_cities()?
+        .into_iter()
+        .map(|city| city.city_data)
+        .collect();
+
+    let (cities, summary) = merge_city_lists(&current, imported, mode);
+    data_controller.set_cities(cities)?;
+    Ok(summary)
+}
+
+// Where the application exports the list to, the desktop offers it for the import as well.
+pub fn city_list_location() -> String {
+    #[cfg(target_os = "android")]
+    {
+        androidstorage::download_location(CITY_LIST_FILE_NAME)
+    }
+
+    #[cfg(not(target_os = "android"))]
+    {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(dir) = export_dir() {
+            return dir.join(CITY_LIST_FILE_NAME).display().to_string();
+        }
+        CITY_LIST_FILE_NAME.to_string()
+    }
+}
+
+// Saves the list in the download directory, or offers it as a download in the browser.
+pub fn save_city_list(
+    data_controller: &WeatherControllerPointer,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    save_file(
+        CITY_LIST_FILE_NAME,
+        "application/json",
+        &export_city_list(data_controller)?,
+    )
+}
+
+// Reads the list saved by save_city_list, e.g. copied there from another device.
+#[cfg(target_os = "android")]
+pub fn read_saved_city_list() -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    androidstorage::read_download(CITY_LIST_FILE_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn city(name: &str, lat: f64, lon: f64) -> CityData {
+        CityData {
+            lat,
+            lon,
+            city_name: name.to_string(),
+            label: None,
+            note: None,
+        }
+    }
+
+    fn names(cities: &[CityData]) -> Vec<&str> {
+        cities.iter().map(|city| city.city_name.as_str()).collect()
+    }
+
+    #[test]
+    fn list_is_written_and_read() {
+        let mut cities = vec![
+            city("Berlin", 52.52, 13.405),
+            city("Site B-12", -33.87, 151.21),
+        ];
+        cities[0].annotate(Some("Office".to_string()), Some("3rd floor".to_string()));
+
+        let json = city_list_to_json(&cities).unwrap();
+        assert!(json.contains("\"format\": \"rusty-weather-cities\""));
+        assert_eq!(city_list_from_json(&json).unwrap(), cities);
+    }
+
+    #[test]
+    fn invalid_lists_are_rejected() {
+        assert!(city_list_from_json("[]").is_err());
+        assert!(city_list_from_json(
+            r#"{"format": "rusty-weather-forecast", "version": 1, "cities": []}"#
+        )
+        .is_err());
+        assert!(city_list_from_json(
+            r#"{"format": "rusty-weather-cities", "version": 2, "cities": []}"#
+        )
+        .is_err());
+        assert!(city_list_from_json(
+            r#"{"format": "rusty-weather-cities", "version": 1,
+                "cities": [{"name": "Nowhere", "lat": 95.0, "lon": 0.0}]}"#
+        )
+        .is_err());
+
+        let cities = city_list_from_json(
+            r#"{"format": "rusty-weather-cities", "version": 1,
+                "cities": [{"name": " ", "lat": 52.52, "lon": 13.405}]}"#,
+        )
+        .unwrap();
+        assert_eq!(cities[0].city_name, "52.5200° N, 13.4050° E");
+    }
+
+    #[test]
+    fn merge_keeps_the_current_cities() {
+        let current = vec![
+            city("Berlin", 52.52, 13.405),
+            city("Vienna", 48.2085, 16.3721),
+        ];
+        let imported = vec![
+            city("Office", 52.52, 13.405),
+            city("Miami", 25.7743, -80.1937),
+            city("Miami Beach office", 25.77431, -80.19371),
+        ];
+
+        let (cities, summary) = merge_city_lists(&current, imported, ImportMode::Merge);
+        assert_eq!(names(&cities), ["Berlin", "Vienna", "Miami"]);
+        assert_eq!(
+            summary,
+            ImportSummary {
+                added: 1,
+                duplicates: 2,
+                removed: 0
+            }
+        );
+    }
+
+    #[test]
+    fn replace_takes_the_imported_cities() {
+        let current = vec![
+            city("Berlin", 52.52, 13.405),
+            city("Vienna", 48.2085, 16.3721),
+        ];
+        let imported = vec![
+            city("Miami", 25.7743, -80.1937),
+            city("Office", 52.52, 13.405),
+            city("Miami", 25.7743, -80.1937),
+        ];
+
+        let (cities, summary) = merge_city_lists(&current, imported, ImportMode::Replace);
+        assert_eq!(names(&cities), ["Miami", "Office"]);
+        assert_eq!(
+            summary,
+            ImportSummary {
+                added: 1,
+                duplicates: 1,
+                removed: 1
+            }
+        );
+    }
+}
@@ -0,0 +1,543 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::io;
+
+use crate::weather::statestore::StateStore;
+use crate::weather::weathercontroller::{CityData, WeatherAlert, WeatherData};
+
+// Version of the stored cities format, increased with each incompatible change:
+// 1 - list of the provider clients with the raw api responses,
+// 2 - provider independent cities with the converted weather data.
+pub const CITIES_STORAGE_VERSION: u64 = 2;
+
+// All the providers store the cities under this key, so the list is kept when the provider changes.
+pub const CITIES_STORED_KEY: &str = "cities_data.json";
+
+// Number of the previous saves kept next to the stored data.
+const BACKUP_COUNT: usize = 3;
+
+// Converts the raw api response stored by the version 1 into the current weather format.
+pub type LegacyWeatherConverter = dyn Fn(Value) -> Option<StoredWeather>;
+
+// Weather of a city as shown in the application, independent of the provider.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct StoredWeather {
+    pub weather_data: WeatherData,
+    #[serde(default)]
+    pub alerts: Vec<WeatherAlert>,
+    #[serde(default)]
+    pub observed_at: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StoredCity {
+    pub city_data: CityData,
+    #[serde(default)]
+    pub fetched_at: Option<i64>,
+    // the city is kept without the weather if the weather cannot be parsed
+    #[serde(default, deserialize_with = "deserialize_weather")]
+    pub weather: Option<StoredWeather>,
+}
+
+#[derive(Default)]
+pub struct LoadedCities {
+    pub cities: Vec<StoredCity>,
+    // the stored data was damaged, the cities come from a backup
+    pub recovered: bool,
+}
+
+#[derive(Serialize)]
+struct StoredCities<'a> {
+    version: u64,
+    // provider of the stored weather
+    provider: &'a str,
+    cities: &'a [StoredCity],
+}
+
+fn deserialize_weather<'de, D>(deserializer: D) -> Result<Option<StoredWeather>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Value::deserialize(deserializer)?;
+    if value.is_null() {
+        return Ok(None);
+    }
+
+    match serde_json::from_value(value) {
+        Ok(weather) => Ok(Some(weather)),
+        Err(e) => {
+            log::warn!("Stored weather dropped, it cannot be parsed: {}", e);
+            Ok(None)
+        }
+    }
+}
+
+fn invalid_data(message: String) -> Box<dyn std::error::Error + Send + Sync> {
+    Box::new(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+// The newest backup comes first, e.g. "cities_data.json.1".
+fn backup_keys(key: &str) -> Vec<String> {
+    (1..=BACKUP_COUNT)
+        .map(|index| format!("{}.{}", key, index))
+        .collect()
+}
+
+// Falls back to the newest backup that can be read when the stored data is damaged or missing,
+// nothing is returned when no cities were ever saved.
+// The weather of another provider is kept, but marked as outdated to be fetched again.
+pub fn read_stored_cities(
+    state_store: &dyn StateStore,
+    key: &str,
+    provider: &str,
+    legacy_weather: &LegacyWeatherConverter,
+) -> Result<Option<LoadedCities>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut stored = false;
+    let mut damaged = false;
+    let mut read_key =
+        |key: &str| -> Result<Vec<StoredCity>, Box<dyn std::error::Error + Send + Sync>> {
+            match state_store.read(key)? {
+                Some(data) => {
+                    stored = true;
+                    let (stored_provider, mut cities) = parse_stored_cities(&data, legacy_weather)
+                        .inspect_err(|_| damaged = true)?;
+                    if stored_provider.as_deref() != Some(provider) {
+                        for city in &mut cities {
+                            city.fetched_at = None;
+                        }
+                    }
+                    Ok(cities)
+                }
+                None => Err(Box::new(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No data stored under: {}", key),
+                ))),
+            }
+        };
+
+    let error = match read_key(key) {
+        Ok(cities) => {
+            return Ok(Some(LoadedCities {
+                cities,
+                recovered: false,
+            }))
+        }
+        Err(e) => e,
+    };
+
+    for backup_key in backup_keys(key) {
+        match read_key(&backup_key) {
+            Ok(cities) => {
+                log::warn!(
+                    "Stored cities could not be read ({}), restored from: {}",
+                    error,
+                    backup_key
+                );
+                return Ok(Some(LoadedCities {
+                    cities,
+                    recovered: damaged,
+                }));
+            }
+            Err(e) => log::debug!("Backup {} not used: {}", backup_key, e),
+        }
+    }
+
+    match stored {
+        true => Err(error),
+        false => Ok(None),
+    }
+}
+
+// The previously stored data is kept as the newest backup when the list of the cities changes,
+// the weather updates do not replace the backups.
+pub fn write_stored_cities(
+    state_store: &dyn StateStore,
+    key: &str,
+    provider: &str,
+    cities: &[StoredCity],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let data = serialize_cities(provider, cities)?;
+
+    // a failed backup does not prevent saving the current data
+    if let Err(e) = rotate_backups(state_store, key, cities) {
+        log::warn!("Failed to back up the stored cities: {}", e);
+    }
+    state_store.write(key, &data)
+}
+
+fn rotate_backups(
+    state_store: &dyn StateStore,
+    key: &str,
+    cities: &[StoredCity],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let Some(current_data) = state_store.read(key)? else {
+        return Ok(());
+    };
+
+    // damaged data is not kept, it would push out a readable backup
+    let current_cities = parse_stored_cities(&current_data, &|_| None)?.1;
+    if current_cities
+        .iter()
+        .map(|city| &city.city_data)
+        .eq(cities.iter().map(|city| &city.city_data))
+    {
+        return Ok(());
+    }
+
+    let backup_keys = backup_keys(key);
+    for index in (1..backup_keys.len()).rev() {
+        if let Some(data) = state_store.read(&backup_keys[index - 1])? {
+            state_store.write(&backup_keys[index], &data)?;
+        }
+    }
+
+    state_store.write(&backup_keys[0], &current_data)
+}
+
+// The previous versions stored the cities of each provider under its own key.
+pub fn migrate_legacy_key(
+    state_store: &dyn StateStore,
+    legacy_key: &str,
+    key: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if state_store.read(key)?.is_some() {
+        return Ok(());
+    }
+    match state_store.read(legacy_key)? {
+        Some(data) => {
+            log::info!("Moving the stored cities from {} to {}", legacy_key, key);
+            state_store.write(key, &data)
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+pub fn parse_cities(
+    data: &str,
+    legacy_weather: &LegacyWeatherConverter,
+) -> Result<Vec<StoredCity>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(parse_stored_cities(data, legacy_weather)?.1)
+}
+
+// Returns the provider of the stored weather, unknown for the data saved by the previous versions.
+fn parse_stored_cities(
+    data: &str,
+    legacy_weather: &LegacyWeatherConverter,
+) -> Result<(Option<String>, Vec<StoredCity>), Box<dyn std::error::Error + Send + Sync>> {
+    let value: Value = serde_json::from_str(data)?;
+    let value = migrate(value, legacy_weather)?;
+    let provider = value
+        .get("provider")
+        .and_then(Value::as_str)
+        .map(str::to_string);
+
+    let cities = match value.get("cities") {
+        Some(Value::Array(cities)) => cities,
+        _ => return Err(invalid_data("Stored cities list not found".to_string())),
+    };
+
+    // a broken entry does not prevent loading the other cities
+    let cities = cities
+        .iter()
+        .filter_map(|city| match StoredCity::deserialize(city) {
+            Ok(city) => Some(city),
+            Err(e) => {
+                log::warn!("Stored city dropped, it cannot be parsed: {}", e);
+                None
+            }
+        })
+        .collect();
+    Ok((provider, cities))
+}
+
+pub fn serialize_cities(
+    provider: &str,
+    cities: &[StoredCity],
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let stored_cities = StoredCities {
+        version: CITIES_STORAGE_VERSION,
+        provider,
+        cities,
+    };
+    Ok(serde_json::to_string(&stored_cities)?)
+}
+
+// Upgrades the stored data step by step to the current version.
+fn migrate(
+    mut value: Value,
+    legacy_weather: &LegacyWeatherConverter,
+) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    loop {
+        // the first version has no version field, it is a plain list
+        let version = match &value {
+            Value::Array(_) => 1,
+            _ => value
+                .get("version")
+                .and_then(Value::as_u64)
+                .ok_or_else(|| invalid_data("Stored cities version not found".to_string()))?,
+        };
+
+        match version {
+            CITIES_STORAGE_VERSION => return Ok(value),
+            1 => {
+                log::info!("Migrating stored cities from version 1");
+                value = migrate_from_v1(value, legacy_weather);
+            }
+            _ => {
+                return Err(invalid_data(format!(
+                    "Unsupported stored cities version: {}",
+                    version
+                )))
+            }
+        }
+    }
+}
+
+fn migrate_from_v1(value: Value, legacy_weather: &LegacyWeatherConverter) -> Value {
+    let cities: Vec<Value> = match value {
+        Value::Array(clients) => clients
+            .into_iter()
+            .map(|mut client| {
+                let weather = client
+                    .get_mut("weather_data")
+                    .map(Value::take)
+                    .filter(|weather_data| !weather_data.is_null())
+                    .and_then(legacy_weather);
+
+                serde_json::json!({
+                    "city_data": client.get("city_data").cloned().unwrap_or_default(),
+                    "fetched_at": client.get("fetched_at").cloned().unwrap_or_default(),
+                    "weather": serde_json::to_value(weather).unwrap_or_default(),
+                })
+            })
+            .collect(),
+        _ => vec![],
+    };
+
+    serde_json::json!({
+        "version": 2,
+        "cities": cities,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::weather::statestore::MemoryStateStore;
+    use crate::weather::weathercontroller::DayWeatherData;
+
+    fn legacy_weather(value: Value) -> Option<StoredWeather> {
+        let temperature = value.get("temp")?.as_f64()?;
+        Some(StoredWeather {
+            weather_data: WeatherData {
+                current_data: DayWeatherData {
+                    current_temperature: temperature,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn cities_are_written_and_read_back() {
+        let cities = vec![StoredCity {
+            city_data: CityData {
+                lat: 52.52,
+                lon: 13.41,
+                city_name: "Berlin".to_string(),
+                label: Some("Office".to_string()),
+                note: Some("Key at the reception".to_string()),
+            },
+            fetched_at: Some(1709535600),
+            weather: Some(StoredWeather::default()),
+        }];
+
+        let data = serialize_cities("test", &cities).unwrap();
+        let read = parse_cities(&data, &legacy_weather).unwrap();
+
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].city_data, cities[0].city_data);
+        assert_eq!(read[0].fetched_at, Some(1709535600));
+        assert!(read[0].weather.is_some());
+    }
+
+    #[test]
+    fn version_1_is_migrated() {
+        let stored = r#"[
+            {"city_data":{"lat":52.52,"lon":13.41,"city_name":"Berlin"},"fetched_at":1709535600,"weather_data":{"temp":2.5}},
+            {"city_data":{"lat":48.14,"lon":11.58,"city_name":"Munich"},"weather_data":null}
+        ]"#;
+
+        let cities = parse_cities(stored, &legacy_weather).unwrap();
+        assert_eq!(cities.len(), 2);
+        assert_eq!(cities[0].city_data.city_name, "Berlin");
+        assert_eq!(cities[0].fetched_at, Some(1709535600));
+        let weather = cities[0].weather.as_ref().unwrap();
+        assert_eq!(weather.weather_data.current_data.current_temperature, 2.5);
+        assert_eq!(cities[1].city_data.city_name, "Munich");
+        assert!(cities[1].weather.is_none());
+    }
+
+    #[test]
+    fn unparsable_weather_keeps_the_city() {
+        let stored = r#"{"version":2,"cities":[
+            {"city_data":{"lat":52.52,"lon":13.41,"city_name":"Berlin"},"weather":{"weather_data":"broken"}},
+            {"city_data":"broken"}
+        ]}"#;
+
+        let cities = parse_cities(stored, &legacy_weather).unwrap();
+        assert_eq!(cities.len(), 1);
+        assert_eq!(cities[0].city_data.city_name, "Berlin");
+        assert!(cities[0].weather.is_none());
+    }
+
+    fn city(name: &str) -> StoredCity {
+        StoredCity {
+            city_data: CityData {
+                lat: 52.52,
+                lon: 13.41,
+                city_name: name.to_string(),
+                label: None,
+                note: None,
+            },
+            fetched_at: None,
+            weather: None,
+        }
+    }
+
+    #[test]
+    fn previous_saves_are_kept_as_backups() {
+        let state_store = MemoryStateStore::default();
+        for name in ["Berlin", "Munich", "Hamburg", "Cologne", "Dresden"] {
+            write_stored_cities(&state_store, "cities", "test", &[city(name)]).unwrap();
+        }
+
+        let loaded = read_stored_cities(&state_store, "cities", "test", &legacy_weather)
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.cities[0].city_data.city_name, "Dresden");
+        assert!(!loaded.recovered);
+
+        let backups: Vec<String> = backup_keys("cities")
+            .iter()
+            .map(|backup_key| {
+                let data = state_store.read(backup_key).unwrap().unwrap();
+                parse_cities(&data, &legacy_weather).unwrap()[0]
+                    .city_data
+                    .city_name
+                    .clone()
+            })
+            .collect();
+        assert_eq!(backups, ["Cologne", "Hamburg", "Munich"]);
+    }
+
+    #[test]
+    fn damaged_data_is_recovered_from_backup() {
+        let state_store = MemoryStateStore::default();
+        write_stored_cities(&state_store, "cities", "test", &[city("Berlin")]).unwrap();
+        write_stored_cities(&state_store, "cities", "test", &[city("Munich")]).unwrap();
+
+        // truncated write
+        state_store
+            .write("cities", r#"{"version":2,"cities":[{"city_da"#)
+            .unwrap();
+
+        let loaded = read_stored_cities(&state_store, "cities", "test", &legacy_weather)
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.cities[0].city_data.city_name, "Berlin");
+        assert!(loaded.recovered);
+    }
+
+    #[test]
+    fn weather_updates_keep_the_backups() {
+        let state_store = MemoryStateStore::default();
+        write_stored_cities(&state_store, "cities", "test", &[city("Berlin")]).unwrap();
+        write_stored_cities(&state_store, "cities", "test", &[city("Munich")]).unwrap();
+
+        let mut munich = city("Munich");
+        for fetched_at in 1..=5 {
+            munich.fetched_at = Some(fetched_at);
+            write_stored_cities(&state_store, "cities", "test", &[munich.clone()]).unwrap();
+        }
+
+        let data = state_store.read("cities.1").unwrap().unwrap();
+        let backup = parse_cities(&data, &legacy_weather).unwrap();
+        assert_eq!(backup[0].city_data.city_name, "Berlin");
+        assert!(state_store.read("cities.2").unwrap().is_none());
+    }
+
+    #[test]
+    fn missing_data_is_restored_without_notice() {
+        let state_store = MemoryStateStore::default();
+        state_store
+            .write(
+                "cities.1",
+                &serialize_cities("test", &[city("Berlin")]).unwrap(),
+            )
+            .unwrap();
+
+        let loaded = read_stored_cities(&state_store, "cities", "test", &legacy_weather)
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.cities[0].city_data.city_name, "Berlin");
+        assert!(!loaded.recovered);
+    }
+
+    #[test]
+    fn nothing_is_read_before_the_first_save() {
+        let state_store = MemoryStateStore::default();
+        assert!(
+            read_stored_cities(&state_store, "cities", "test", &legacy_weather)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn damaged_data_without_backups_is_reported() {
+        let state_store = MemoryStateStore::default();
+        state_store.write("cities", "{").unwrap();
+        assert!(read_stored_cities(&state_store, "cities", "test", &legacy_weather).is_err());
+    }
+
+    #[test]
+    fn weather_of_another_provider_is_outdated() {
+        let state_store = MemoryStateStore::default();
+        let mut berlin = city("Berlin");
+        berlin.fetched_at = Some(1709535600);
+        berlin.weather = Some(StoredWeather::default());
+        write_stored_cities(&state_store, "cities", "test", &[berlin]).unwrap();
+
+        let loaded = read_stored_cities(&state_store, "cities", "other", &legacy_weather)
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.cities[0].city_data.city_name, "Berlin");
+        assert_eq!(loaded.cities[0].fetched_at, None);
+        assert!(loaded.cities[0].weather.is_some());
+    }
+
+    #[test]
+    fn legacy_key_is_moved() {
+        let state_store = MemoryStateStore::default();
+        write_stored_cities(&state_store, "legacy", "test", &[city("Berlin")]).unwrap();
+        migrate_legacy_key(&state_store, "legacy", "cities").unwrap();
+        write_stored_cities(&state_store, "legacy", "test", &[city("Munich")]).unwrap();
+        // the cities are moved only once
+        migrate_legacy_key(&state_store, "legacy", "cities").unwrap();
+
+        let loaded = read_stored_cities(&state_store, "cities", "test", &legacy_weather)
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.cities[0].city_data.city_name, "Berlin");
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let stored = r#"{"version":3,"cities":[]}"#;
+        assert!(parse_cities(stored, &legacy_weather).is_err());
+    }
+}
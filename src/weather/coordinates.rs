@@ -0,0 +1,329 @@
+use crate::weather::gazetteer;
+use crate::weather::weathercontroller::{GeoLocationData, WeatherControllerPointer};
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Unit {
+    Degrees,
+    Minutes,
+    Seconds,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Token {
+    Number(f64, Option<Unit>),
+    Hemisphere(char),
+    Separator,
+}
+
+// One coordinate: degrees with the optional minutes and seconds and the hemisphere letter.
+#[derive(Default)]
+struct Component {
+    values: Vec<f64>,
+    hemisphere: Option<char>,
+}
+
+fn unit_from_char(c: char) -> Option<Unit> {
+    match c {
+        '°' | 'º' => Some(Unit::Degrees),
+        '\'' | '′' | '’' => Some(Unit::Minutes),
+        '"' | '″' | '”' => Some(Unit::Seconds),
+        _ => None,
+    }
+}
+
+// None when the text contains anything else than the numbers, units, hemispheres and separators,
+// so the place names are never taken for coordinates.
+fn tokenize(text: &str) -> Option<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+
+        if c == ',' || c == ';' {
+            tokens.push(Token::Separator);
+        } else if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' {
+            let mut number = c.to_string();
+            while let Some(&next) = chars.peek() {
+                if !next.is_ascii_digit() && next != '.' {
+                    break;
+                }
+                number.push(next);
+                chars.next();
+            }
+
+            // seconds are sometimes written with two apostrophes
+            let mut unit = chars.peek().copied().and_then(unit_from_char);
+            if unit.is_some() {
+                chars.next();
+            }
+            if unit == Some(Unit::Minutes) && chars.peek() == Some(&'\'') {
+                chars.next();
+                unit = Some(Unit::Seconds);
+            }
+
+            tokens.push(Token::Number(number.parse().ok()?, unit));
+        } else if c.is_alphabetic() {
+            let hemisphere = c.to_ascii_uppercase();
+            let standalone = chars.peek().is_none_or(|next| !next.is_alphabetic());
+            if !standalone || !matches!(hemisphere, 'N' | 'S' | 'E' | 'W') {
+                return None;
+            }
+            tokens.push(Token::Hemisphere(hemisphere));
+        } else {
+            return None;
+        }
+    }
+
+    Some(tokens)
+}
+
+fn components(tokens: &[Token]) -> Option<Vec<Component>> {
+    let mut components = vec![];
+    let mut current = Component::default();
+
+    for token in tokens {
+        match *token {
+            Token::Separator => {
+                if !current.values.is_empty() || current.hemisphere.is_some() {
+                    components.push(std::mem::take(&mut current));
+                }
+            }
+            Token::Number(value, unit) => {
+                let expected_unit = match current.values.len() {
+                    0 => Unit::Degrees,
+                    1 => Unit::Minutes,
+                    _ => Unit::Seconds,
+                };
+                // degrees start the next coordinate, e.g. "52°31' 13°24'"
+                if unit == Some(Unit::Degrees) && !current.values.is_empty() {
+                    components.push(std::mem::take(&mut current));
+                } else if unit.is_some_and(|unit| unit != expected_unit) {
+                    return None;
+                }
+                if current.values.len() == 3 {
+                    return None;
+                }
+                current.values.push(value);
+            }
+            Token::Hemisphere(hemisphere) => {
+                if current.hemisphere.is_some() {
+                    // the letter is a prefix of the next coordinate, e.g. "N 52.5 E 13.4"
+                    components.push(std::mem::take(&mut current));
+                    current.hemisphere = Some(hemisphere);
+                } else if current.values.is_empty() {
+                    current.hemisphere = Some(hemisphere);
+                } else {
+                    current.hemisphere = Some(hemisphere);
+                    components.push(std::mem::take(&mut current));
+                }
+            }
+        }
+    }
+    if !current.values.is_empty() || current.hemisphere.is_some() {
+        components.push(current);
+    }
+
+    // two plain decimal numbers, e.g. "52.52 13.41"
+    let plain = tokens
+        .iter()
+        .all(|token| matches!(token, Token::Number(_, None)));
+    if plain && components.len() == 1 && components[0].values.len() == 2 {
+        let values = &components[0].values;
+        return Some(vec![
+            Component {
+                values: vec![values[0]],
+                hemisphere: None,
+            },
+            Component {
+                values: vec![values[1]],
+                hemisphere: None,
+            },
+        ]);
+    }
+
+    Some(components)
+}
+
+impl Component {
+    fn decimal_degrees(&self) -> Option<f64> {
+        let degrees = *self.values.first()?;
+        let fraction = match self.values[1..] {
+            [] => 0.0,
+            [minutes] if (0.0..60.0).contains(&minutes) => minutes / 60.0,
+            [minutes, seconds]
+                if (0.0..60.0).contains(&minutes) && (0.0..60.0).contains(&seconds) =>
+            {
+                minutes / 60.0 + seconds / 3600.0
+            }
+            _ => return None,
+        };
+        let value = degrees.abs() + fraction;
+
+        match self.hemisphere {
+            None => Some(value.copysign(degrees)),
+            Some('S' | 'W') if degrees >= 0.0 => Some(-value),
+            Some(_) if degrees >= 0.0 => Some(value),
+            // "-52.5 S" is ambiguous
+            Some(_) => None,
+        }
+    }
+
+    fn is_longitude(&self) -> bool {
+        matches!(self.hemisphere, Some('E' | 'W'))
+    }
+
+    fn is_latitude(&self) -> bool {
+        matches!(self.hemisphere, Some('N' | 'S'))
+    }
+}
+
+// geo:52.52,13.41 or geo:52.52,13.41,34;u=35 (RFC 5870), the altitude and parameters are ignored.
+fn parse_geo_uri(uri: &str) -> Option<(f64, f64)> {
+    let coordinates = uri.split(';').next()?;
+    let mut values = coordinates
+        .split(',')
+        .map(|value| value.trim().parse::<f64>());
+    let lat = values.next()?.ok()?;
+    let lon = values.next()?.ok()?;
+    Some((lat, lon))
+}
+
+// Latitude and longitude from the decimal degrees ("52.52, 13.41", "-33.87 151.21"),
+// degrees with minutes and seconds ("52°31'12\" N 13°24'36\" E") or a geo URI.
+pub fn parse_coordinates(text: &str) -> Option<(f64, f64)> {
+    let text = text.trim();
+
+    let (lat, lon) = match text.get(..4) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("geo:") => parse_geo_uri(&text[4..])?,
+        _ => {
+            let components = components(&tokenize(text)?)?;
+            let [first, second] = &components[..] else {
+                return None;
+            };
+
+            // the longitude can be given first when both have the hemisphere letter
+            let (lat, lon) = if first.is_longitude() && second.is_latitude() {
+                (second, first)
+            } else {
+                (first, second)
+            };
+            if lat.is_longitude() || lon.is_latitude() {
+                return None;
+            }
+            (lat.decimal_degrees()?, lon.decimal_degrees()?)
+        }
+    };
+
+    ((-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon)).then_some((lat, lon))
+}
+
+// E.g. "52.5200° N, 13.4050° E".
+pub fn format_coordinates(lat: f64, lon: f64) -> String {
+    format!(
+        "{:.4}° {}, {:.4}° {}",
+        lat.abs(),
+        if lat < 0.0 { 'S' } else { 'N' },
+        lon.abs(),
+        if lon < 0.0 { 'W' } else { 'E' }
+    )
+}
+
+// Location at exactly the given coordinates, named after the place found there if any.
+pub fn location_at(lat: f64, lon: f64, place: Option<GeoLocationData>) -> GeoLocationData {
+    match place {
+        Some(place) => GeoLocationData { lat, lon, ..place },
+        None => GeoLocationData {
+            name: format_coordinates(lat, lon),
+            lat,
+            lon,
+            country: String::new(),
+            state: None,
+            timezone: None,
+        },
+    }
+}
+
+// The entered coordinates are kept as they are, only the name comes from the reverse geocoding.
+pub async fn locate_coordinates(
+    data_controller: &WeatherControllerPointer,
+    lat: f64,
+    lon: f64,
+) -> GeoLocationData {
+    let place = match data_controller.reverse_geocode(lat, lon).await {
+        Ok(place) => place,
+        Err(e) => {
+            log::warn!("Failed to reverse geocode {}, {}: {}.", lat, lon, e);
+            gazetteer::reverse_geocode(lat, lon)
+        }
+    };
+    location_at(lat, lon, place)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_coordinates(text: &str, lat: f64, lon: f64) {
+        let (parsed_lat, parsed_lon) =
+            parse_coordinates(text).unwrap_or_else(|| panic!("not parsed: {}", text));
+        assert!(
+            (parsed_lat - lat).abs() < 1e-6 && (parsed_lon - lon).abs() < 1e-6,
+            "{}: {} {}",
+            text,
+            parsed_lat,
+            parsed_lon
+        );
+    }
+
+    #[test]
+    fn decimal_degrees_are_parsed() {
+        assert_coordinates("52.52, 13.41", 52.52, 13.41);
+        assert_coordinates("52.52,13.41", 52.52, 13.41);
+        assert_coordinates("-33.87 151.21", -33.87, 151.21);
+        assert_coordinates("  64.1355; -21.8954 ", 64.1355, -21.8954);
+        assert_coordinates("33.87 S, 151.21 E", -33.87, 151.21);
+        assert_coordinates("N 52.52 W 1.5", 52.52, -1.5);
+        assert_coordinates("52.52° N, 13.41° E", 52.52, 13.41);
+    }
+
+    #[test]
+    fn degrees_minutes_seconds_are_parsed() {
+        assert_coordinates("52°31'12\"N 13°24'36\"E", 52.52, 13.41);
+        assert_coordinates("52° 31′ 12″ N, 13° 24′ 36″ E", 52.52, 13.41);
+        assert_coordinates("33°52'S 151°12.6'E", -(33.0 + 52.0 / 60.0), 151.21);
+        assert_coordinates("13°24'36''E 52°31'12''N", 52.52, 13.41);
+    }
+
+    #[test]
+    fn geo_uri_is_parsed() {
+        assert_coordinates("geo:52.52,13.41", 52.52, 13.41);
+        assert_coordinates("GEO:-33.87,151.21,25;u=35", -33.87, 151.21);
+    }
+
+    #[test]
+    fn other_text_is_rejected() {
+        for text in [
+            "Berlin",
+            "Essen",
+            "52.52",
+            "10 Downing Street",
+            "91, 13",
+            "52.52, 181",
+            "52°70' N 13° E",
+            "13.41 E, 52.52 E",
+            "geo:52.52",
+            "",
+        ] {
+            assert_eq!(parse_coordinates(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn unnamed_location_shows_the_coordinates() {
+        let location = location_at(-33.8688, 151.2093, None);
+        assert_eq!(location.name, "33.8688° S, 151.2093° E");
+    }
+}
@@ -1,18 +1,42 @@
-use chrono::{Duration, Utc};
+use async_trait::async_trait;
+use chrono::{Duration, DurationRound, Local, Timelike, Utc};
+use std::sync::Mutex;
 
-use crate::weather::utils::*;
+use crate::weather::citystorage::{
+    migrate_legacy_key, read_stored_cities, write_stored_cities, StoredCity, CITIES_STORED_KEY,
+};
+use crate::weather::gazetteer;
+use crate::weather::statestore::{default_state_store, StateStorePointer};
+use crate::weather::syntheticweather::generate_weather_data;
+use crate::weather::undo::UndoBuffer;
 use crate::weather::weathercontroller::{
-    CityData, CityWeatherData, GeoLocationData, WeatherController,
+    index_out_of_bounds, move_element, remove_element, CityData, CityWeatherData, DayWeatherData,
+    ForecastWeatherData, GeoLocationData, HourlyWeatherData, LoadStatus, MinutelyWeatherData,
+    PrecipitationData, RefreshStatus, WeatherController,
 };
 
+const HOURLY_FORECAST_LENGTH: i64 = 48;
+const MINUTELY_FORECAST_LENGTH: i64 = 60;
+const MINUTELY_RAIN_START: i64 = 12;
+
+// Only the list of the cities is stored, the weather is generated on each load.
+// name of the provider of the stored weather
+const PROVIDER_NAME: &str = "dummy";
+// key of the cities stored by the previous versions
+const LEGACY_CITIES_STORED_KEY: &str = "dummy_cities_data.json";
+
 pub struct DummyWeatherController {
-    city_weather_data: Vec<CityWeatherData>,
+    city_weather_data: Mutex<Vec<CityWeatherData>>,
+    removed_cities: Mutex<UndoBuffer<CityWeatherData>>,
+    state_store: StateStorePointer,
 }
 
 impl DummyWeatherController {
     pub fn new() -> Self {
         Self {
-            city_weather_data: vec![],
+            city_weather_data: Mutex::new(vec![]),
+            removed_cities: Mutex::new(UndoBuffer::new()),
+            state_store: default_state_store(),
         }
     }
 
@@ -21,18 +45,37 @@ impl DummyWeatherController {
 
         match serde_json::from_str::<Vec<CityWeatherData>>(json_data) {
             Ok(weather_data) => {
-                // fix day names
+                // fix forecast days
                 let mut weather_data = weather_data.clone();
                 for city_data in &mut weather_data {
                     let forecast_data = &mut (city_data.weather_data.forecast_data);
                     for (index, data) in forecast_data.iter_mut().enumerate() {
-                        if index == 0 {
-                            data.day_name = "Today".into();
-                        } else {
-                            data.day_name =
-                                get_day_from_datetime(Utc::now() + Duration::days(index as i64));
-                        }
+                        data.timestamp = (Utc::now() + Duration::days(index as i64)).timestamp();
+                    }
+
+                    city_data.weather_data.hourly_data =
+                        Self::generate_hourly_data(&city_data.weather_data.forecast_data);
+                    city_data.weather_data.minutely_data =
+                        Self::generate_minutely_data(&city_data.weather_data.current_data);
+
+                    // sunrise and sunset are stored relative to the local midnight
+                    let current_data = &mut city_data.weather_data.current_data;
+                    let midnight = Local::now()
+                        .date_naive()
+                        .and_hms_opt(0, 0, 0)
+                        .and_then(|midnight| midnight.and_local_timezone(Local).single())
+                        .map_or(0, |midnight| midnight.timestamp());
+                    current_data.sunrise = current_data.sunrise.map(|time| time + midnight);
+                    current_data.sunset = current_data.sunset.map(|time| time + midnight);
+
+                    // alert times are stored relative to now
+                    for alert in &mut city_data.alerts {
+                        alert.start += Utc::now().timestamp();
+                        alert.end += Utc::now().timestamp();
                     }
+
+                    city_data.fetched_at = Some(Utc::now().timestamp());
+                    city_data.observed_at = city_data.fetched_at;
                 }
 
                 return weather_data;
@@ -44,49 +87,388 @@ impl DummyWeatherController {
 
         vec![]
     }
+
+    // Cities other than the predefined ones get the synthetic weather.
+    fn generate_city_data(city: &CityData) -> CityWeatherData {
+        let mut weather_data = generate_weather_data(city);
+        weather_data.hourly_data = Self::generate_hourly_data(&weather_data.forecast_data);
+        if let Some(hour_data) = weather_data.hourly_data.first() {
+            weather_data.current_data.current_temperature = hour_data.temperature;
+        }
+        weather_data.minutely_data = Self::generate_minutely_data(&weather_data.current_data);
+
+        let now = Utc::now().timestamp();
+        CityWeatherData {
+            city_data: city.clone(),
+            weather_data,
+            alerts: vec![],
+            refresh_status: RefreshStatus::Fresh,
+            fetched_at: Some(now),
+            observed_at: Some(now),
+        }
+    }
+
+    fn city_data(city: &CityData, dummy_data: &[CityWeatherData]) -> CityWeatherData {
+        match dummy_data
+            .iter()
+            .find(|data| data.city_data.is_same_place(city))
+        {
+            Some(data) => CityWeatherData {
+                city_data: city.clone(),
+                ..data.clone()
+            },
+            None => Self::generate_city_data(city),
+        }
+    }
+
+    fn generate_minutely_data(current_data: &DayWeatherData) -> Vec<MinutelyWeatherData> {
+        let precipitation = &current_data.precipitation;
+        let volume = precipitation.rain_volume.max(precipitation.snow_volume);
+        if precipitation.probability < 0.5 || volume == 0.0 {
+            return vec![];
+        }
+
+        let start = match Utc::now().duration_trunc(Duration::minutes(1)) {
+            Ok(start) => start,
+            Err(_) => return vec![],
+        };
+
+        // dry start followed by a single shower peaking in the middle
+        let shower_length = (MINUTELY_FORECAST_LENGTH - MINUTELY_RAIN_START) as f64;
+        (0..MINUTELY_FORECAST_LENGTH)
+            .map(|minute| {
+                let intensity = if minute < MINUTELY_RAIN_START {
+                    0.0
+                } else {
+                    let progress = (minute - MINUTELY_RAIN_START) as f64 / shower_length;
+                    volume * (progress * std::f64::consts::PI).sin()
+                };
+
+                MinutelyWeatherData {
+                    timestamp: (start + Duration::minutes(minute)).timestamp(),
+                    precipitation: intensity,
+                }
+            })
+            .collect()
+    }
+
+    fn generate_hourly_data(forecast_data: &[ForecastWeatherData]) -> Vec<HourlyWeatherData> {
+        let start = match Local::now().duration_trunc(Duration::hours(1)) {
+            Ok(start) => start,
+            Err(_) => return vec![],
+        };
+        let start_day = start.date_naive();
+
+        (0..HOURLY_FORECAST_LENGTH)
+            .filter_map(|hour| {
+                let datetime = start + Duration::hours(hour);
+                let day_index = (datetime.date_naive() - start_day).num_days() as usize;
+                let day_weather_data = &forecast_data.get(day_index)?.weather_data;
+
+                let temperature_data = &day_weather_data.detailed_temperature;
+                let temperature = match datetime.hour() {
+                    6..=11 => temperature_data.morning,
+                    12..=17 => temperature_data.day,
+                    18..=21 => temperature_data.evening,
+                    _ => temperature_data.night,
+                };
+
+                let precipitation = &day_weather_data.precipitation;
+                Some(HourlyWeatherData {
+                    timestamp: datetime.timestamp(),
+                    condition: day_weather_data.condition.clone(),
+                    description: day_weather_data.description.clone(),
+                    temperature,
+                    precipitation: PrecipitationData {
+                        probability: precipitation.probability,
+                        rain_volume: precipitation.rain_volume / 24.0,
+                        snow_volume: precipitation.snow_volume / 24.0,
+                    },
+                })
+            })
+            .collect()
+    }
 }
 
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
 impl WeatherController for DummyWeatherController {
-    fn load(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.city_weather_data = Self::generate_dummy_data();
-        Ok(())
+    fn load(&self) -> Result<LoadStatus, Box<dyn std::error::Error + Send + Sync>> {
+        let dummy_data = Self::generate_dummy_data();
+        if let Err(e) = migrate_legacy_key(
+            self.state_store.as_ref(),
+            LEGACY_CITIES_STORED_KEY,
+            CITIES_STORED_KEY,
+        ) {
+            log::warn!("Failed to move the stored cities: {}", e);
+        }
+
+        // the stored list keeps the cities added, removed or reordered by the user
+        let (city_weather_data, load_status) = match read_stored_cities(
+            self.state_store.as_ref(),
+            CITIES_STORED_KEY,
+            PROVIDER_NAME,
+            &|_| None,
+        ) {
+            Ok(Some(loaded_cities)) => (
+                loaded_cities
+                    .cities
+                    .iter()
+                    .map(|stored_city| Self::city_data(&stored_city.city_data, &dummy_data))
+                    .collect(),
+                match loaded_cities.recovered {
+                    true => LoadStatus::Recovered,
+                    false => LoadStatus::Loaded,
+                },
+            ),
+            Ok(None) => (dummy_data, LoadStatus::Loaded),
+            Err(e) => {
+                log::debug!("Stored cities not loaded, using all dummy cities: {}", e);
+                (dummy_data, LoadStatus::Loaded)
+            }
+        };
+
+        *self.city_weather_data.lock().unwrap() = city_weather_data;
+        Ok(load_status)
     }
 
-    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
-        Ok(())
+    fn save(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let stored_cities: Vec<StoredCity> = self
+            .city_weather_data
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|data| StoredCity {
+                city_data: data.city_data.clone(),
+                fetched_at: None,
+                weather: None,
+            })
+            .collect();
+        write_stored_cities(
+            self.state_store.as_ref(),
+            CITIES_STORED_KEY,
+            PROVIDER_NAME,
+            &stored_cities,
+        )
+    }
+
+    async fn refresh_cities(
+        &self,
+    ) -> Result<Vec<CityWeatherData>, Box<dyn std::error::Error + Send + Sync>> {
+        self.cached_cities()
     }
 
-    fn refresh_cities(&mut self) -> Result<Vec<CityWeatherData>, Box<dyn std::error::Error>> {
-        Ok(self.city_weather_data.clone())
+    fn cached_cities(
+        &self,
+    ) -> Result<Vec<CityWeatherData>, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.city_weather_data.lock().unwrap().clone())
     }
 
-    fn add_city(
-        &mut self,
-        _city: CityData,
-    ) -> Result<Option<CityWeatherData>, Box<dyn std::error::Error>> {
-        // not supported for the dummy data
-        unimplemented!();
+    async fn add_city(
+        &self,
+        city: CityData,
+    ) -> Result<Option<CityWeatherData>, Box<dyn std::error::Error + Send + Sync>> {
+        log::debug!("Adding new city: {city:?}");
+
+        let mut city_weather_data = self.city_weather_data.lock().unwrap();
+        if city_weather_data
+            .iter()
+            .any(|data| data.city_data.is_same_place(&city))
+        {
+            log::info!("City already present in list!");
+            return Ok(None);
+        }
+
+        let data = Self::city_data(&city, &Self::generate_dummy_data());
+        city_weather_data.push(data.clone());
+        Ok(Some(data))
     }
 
     fn reorder_cities(
-        &mut self,
+        &self,
         index: usize,
         new_index: usize,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        self.city_weather_data.swap(index, new_index);
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        move_element(
+            &mut self.city_weather_data.lock().unwrap(),
+            index,
+            new_index,
+        )
+    }
+
+    fn remove_city(&self, index: usize) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        remove_element(
+            &mut self.city_weather_data.lock().unwrap(),
+            index,
+            &mut self.removed_cities.lock().unwrap(),
+        )
+    }
+
+    fn undo_remove(&self) -> Result<Vec<usize>, Box<dyn std::error::Error + Send + Sync>> {
+        // locked in the same order as on removal
+        let mut city_weather_data = self.city_weather_data.lock().unwrap();
+        Ok(self
+            .removed_cities
+            .lock()
+            .unwrap()
+            .restore(&mut city_weather_data, Utc::now()))
+    }
+
+    fn annotate_city(
+        &self,
+        index: usize,
+        label: Option<String>,
+        note: Option<String>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut city_weather_data = self.city_weather_data.lock().unwrap();
+        let data = city_weather_data
+            .get_mut(index)
+            .ok_or_else(index_out_of_bounds)?;
+        data.city_data.annotate(label, note);
         Ok(())
     }
 
-    fn remove_city(&mut self, index: usize) -> Result<(), Box<dyn std::error::Error>> {
-        self.city_weather_data.remove(index);
+    fn set_cities(
+        &self,
+        cities: Vec<CityData>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let dummy_data = Self::generate_dummy_data();
+        let mut city_weather_data = self.city_weather_data.lock().unwrap();
+
+        *city_weather_data = cities
+            .into_iter()
+            .map(|city| {
+                match city_weather_data
+                    .iter()
+                    .find(|data| data.city_data.is_same_place(&city))
+                {
+                    Some(data) => CityWeatherData {
+                        city_data: city,
+                        ..data.clone()
+                    },
+                    None => Self::city_data(&city, &dummy_data),
+                }
+            })
+            .collect();
         Ok(())
     }
 
-    fn search_location(
+    async fn search_location(
         &self,
-        _query: String,
-    ) -> Result<Vec<GeoLocationData>, Box<dyn std::error::Error>> {
-        // not supported for the dummy data
-        unimplemented!();
+        query: String,
+    ) -> Result<Vec<GeoLocationData>, Box<dyn std::error::Error + Send + Sync>> {
+        log::debug!("Searching for: {query}");
+
+        Ok(gazetteer::search_location(&query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn city_names(locations: &[GeoLocationData]) -> Vec<&str> {
+        locations
+            .iter()
+            .map(|location| location.name.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn search_location_finds_bundled_cities() {
+        let controller = DummyWeatherController::new();
+
+        let locations = controller
+            .search_location("Zurich".to_string())
+            .await
+            .unwrap();
+        assert_eq!(city_names(&locations), ["Zürich"]);
+
+        let locations = controller.search_location("  ".to_string()).await.unwrap();
+        assert!(locations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn add_city_generates_the_weather() {
+        let controller = DummyWeatherController::new();
+        let city = CityData {
+            lat: 64.1355,
+            lon: -21.8954,
+            city_name: "Reykjav\u{ed}k".to_string(),
+            label: None,
+            note: None,
+        };
+
+        let city_weather = controller.add_city(city.clone()).await.unwrap().unwrap();
+        assert_eq!(city_weather.city_data, city);
+        assert_eq!(city_weather.weather_data.forecast_data.len(), 8);
+        assert!(!city_weather.weather_data.hourly_data.is_empty());
+
+        // the same city is not added twice
+        assert!(controller.add_city(city).await.unwrap().is_none());
+        assert_eq!(controller.cached_cities().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn reorder_cities_moves_the_city() {
+        let controller = DummyWeatherController::new();
+        let cities: Vec<CityData> = ["Berlin", "Vienna", "Miami", "Zürich"]
+            .iter()
+            .enumerate()
+            .map(|(index, name)| CityData {
+                lat: index as f64,
+                lon: 0.0,
+                city_name: name.to_string(),
+                label: None,
+                note: None,
+            })
+            .collect();
+        controller.set_cities(cities).unwrap();
+
+        let names = || -> Vec<String> {
+            controller
+                .cached_cities()
+                .unwrap()
+                .into_iter()
+                .map(|city| city.city_data.city_name)
+                .collect()
+        };
+
+        controller.reorder_cities(3, 0).unwrap();
+        assert_eq!(names(), ["Zürich", "Berlin", "Vienna", "Miami"]);
+        controller.reorder_cities(1, 2).unwrap();
+        assert_eq!(names(), ["Zürich", "Vienna", "Berlin", "Miami"]);
+        assert!(controller.reorder_cities(0, 4).is_err());
+    }
+
+    #[test]
+    fn removed_city_is_restored_with_its_weather() {
+        let controller = DummyWeatherController::new();
+        let cities: Vec<CityData> = ["Berlin", "Vienna", "Miami"]
+            .iter()
+            .enumerate()
+            .map(|(index, name)| CityData {
+                lat: index as f64,
+                lon: 0.0,
+                city_name: name.to_string(),
+                label: None,
+                note: None,
+            })
+            .collect();
+        controller.set_cities(cities).unwrap();
+        let vienna = controller.cached_cities().unwrap().remove(1);
+
+        controller.remove_city(1).unwrap();
+        assert_eq!(controller.cached_cities().unwrap().len(), 2);
+        assert!(controller.remove_city(2).is_err());
+
+        assert_eq!(controller.undo_remove().unwrap(), [1]);
+        let restored = controller.cached_cities().unwrap().remove(1);
+        assert_eq!(restored.city_data, vienna.city_data);
+        assert_eq!(
+            restored.weather_data.current_data.current_temperature,
+            vienna.weather_data.current_data.current_temperature
+        );
+        assert!(controller.undo_remove().unwrap().is_empty());
     }
 }
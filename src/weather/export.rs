@@ -0,0 +1,663 @@
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use serde::Serialize;
+
+use crate::weather::locale::current_locale;
+use crate::weather::units::Units;
+use crate::weather::utils::city_datetime_from_timestamp;
+use crate::weather::weathercontroller::{
+    CityWeatherData, DayWeatherData, ForecastWeatherData, WeatherCondition,
+};
+
+// Version of the JSON export schema, increased on incompatible changes.
+const JSON_SCHEMA_VERSION: u32 = 1;
+
+// Calendar lines longer than this (in bytes, without the line break) are folded (RFC 5545).
+const CALENDAR_LINE_LENGTH: usize = 75;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    Calendar,
+}
+
+impl ExportFormat {
+    #[cfg_attr(any(target_os = "android", target_arch = "wasm32"), allow(dead_code))]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "csv" => Some(ExportFormat::Csv),
+            "json" => Some(ExportFormat::Json),
+            "ics" | "ical" | "calendar" => Some(ExportFormat::Calendar),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+            ExportFormat::Calendar => "ics",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Json => "application/json",
+            ExportFormat::Calendar => "text/calendar",
+        }
+    }
+}
+
+// JSON export schema, all the values are metric (°C, mm, m/s, hPa) whatever the unit settings.
+#[derive(Serialize)]
+struct ForecastExport<'a> {
+    format: &'static str,
+    version: u32,
+    city: CityExport<'a>,
+    // RFC 3339, null when the weather was never fetched
+    fetched_at: Option<String>,
+    days: Vec<DayExport<'a>>,
+}
+
+#[derive(Serialize)]
+struct CityExport<'a> {
+    name: &'a str,
+    lat: f64,
+    lon: f64,
+}
+
+#[derive(Serialize)]
+struct DayExport<'a> {
+    // local date, YYYY-MM-DD
+    date: String,
+    condition: &'static str,
+    description: &'a str,
+    temperature: TemperatureExport,
+    precipitation: PrecipitationExport,
+    uv_index: f64,
+    humidity: f64,
+    pressure: f64,
+    wind_speed: f64,
+    wind_gust: Option<f64>,
+    wind_direction: f64,
+    cloud_cover: f64,
+    sunrise: Option<String>,
+    sunset: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TemperatureExport {
+    min: f64,
+    max: f64,
+    morning: f64,
+    day: f64,
+    evening: f64,
+    night: f64,
+}
+
+#[derive(Serialize)]
+struct PrecipitationExport {
+    // %
+    probability: f64,
+    rain: f64,
+    snow: f64,
+}
+
+const CSV_HEADER: [&str; 21] = [
+    "date",
+    "condition",
+    "description",
+    "temperature_min_c",
+    "temperature_max_c",
+    "temperature_morning_c",
+    "temperature_day_c",
+    "temperature_evening_c",
+    "temperature_night_c",
+    "precipitation_probability_percent",
+    "rain_mm",
+    "snow_mm",
+    "uv_index",
+    "humidity_percent",
+    "pressure_hpa",
+    "wind_speed_ms",
+    "wind_gust_ms",
+    "wind_direction_deg",
+    "cloud_cover_percent",
+    "sunrise",
+    "sunset",
+];
+
+fn condition_name(condition: &WeatherCondition) -> &'static str {
+    match condition {
+        WeatherCondition::Unknown => "unknown",
+        WeatherCondition::Sunny => "sunny",
+        WeatherCondition::PartiallyCloudy => "partially_cloudy",
+        WeatherCondition::MostlyCloudy => "mostly_cloudy",
+        WeatherCondition::Cloudy => "cloudy",
+        WeatherCondition::SunnyRainy => "sunny_rainy",
+        WeatherCondition::Rainy => "rainy",
+        WeatherCondition::Stormy => "stormy",
+        WeatherCondition::Snowy => "snowy",
+        WeatherCondition::Foggy => "foggy",
+    }
+}
+
+fn condition_symbol(condition: &WeatherCondition) -> &'static str {
+    match condition {
+        WeatherCondition::Unknown => "",
+        WeatherCondition::Sunny => "☀",
+        WeatherCondition::PartiallyCloudy => "⛅",
+        WeatherCondition::MostlyCloudy => "🌥",
+        WeatherCondition::Cloudy => "☁",
+        WeatherCondition::SunnyRainy => "🌦",
+        WeatherCondition::Rainy => "🌧",
+        WeatherCondition::Stormy => "⛈",
+        WeatherCondition::Snowy => "❄",
+        WeatherCondition::Foggy => "🌫",
+    }
+}
+
+// The forecast days are dated in the time zone of the city, like in the forecast list.
+fn forecast_date(timestamp: i64, utc_offset: Option<i32>) -> Option<NaiveDate> {
+    city_datetime_from_timestamp(timestamp, utc_offset).map(|date| date.date_naive())
+}
+
+fn local_time(timestamp: i64, utc_offset: Option<i32>) -> Option<String> {
+    city_datetime_from_timestamp(timestamp, utc_offset)
+        .map(|date| date.to_rfc3339_opts(SecondsFormat::Secs, false))
+}
+
+fn to_json(city: &CityWeatherData) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let utc_offset = city.weather_data.utc_offset;
+    let local_time = |timestamp| local_time(timestamp, utc_offset);
+    let days = city
+        .weather_data
+        .forecast_data
+        .iter()
+        .filter_map(|forecast| {
+            let data = &forecast.weather_data;
+            let temperature = &data.detailed_temperature;
+            Some(DayExport {
+                date: forecast_date(forecast.timestamp, utc_offset)?.to_string(),
+                condition: condition_name(&data.condition),
+                description: &data.description,
+                temperature: TemperatureExport {
+                    min: temperature.min,
+                    max: temperature.max,
+                    morning: temperature.morning,
+                    day: temperature.day,
+                    evening: temperature.evening,
+                    night: temperature.night,
+                },
+                precipitation: PrecipitationExport {
+                    probability: data.precipitation.probability * 100.0,
+                    rain: data.precipitation.rain_volume,
+                    snow: data.precipitation.snow_volume,
+                },
+                uv_index: data.uv_index,
+                humidity: data.humidity,
+                pressure: data.pressure,
+                wind_speed: data.wind_speed,
+                wind_gust: data.wind_gust,
+                wind_direction: data.wind_direction,
+                cloud_cover: data.cloud_cover,
+                sunrise: data.sunrise.and_then(local_time),
+                sunset: data.sunset.and_then(local_time),
+            })
+        })
+        .collect();
+
+    let export = ForecastExport {
+        format: "rusty-weather-forecast",
+        version: JSON_SCHEMA_VERSION,
+        city: CityExport {
+            name: &city.city_data.city_name,
+            lat: city.city_data.lat,
+            lon: city.city_data.lon,
+        },
+        fetched_at: city.fetched_at.and_then(local_time),
+        days,
+    };
+    Ok(serde_json::to_string_pretty(&export)?)
+}
+
+// Quoted only when needed (RFC 4180).
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_row(forecast: &ForecastWeatherData, utc_offset: Option<i32>) -> Option<Vec<String>> {
+    let data = &forecast.weather_data;
+    let local_time = |timestamp| local_time(timestamp, utc_offset);
+    let temperature = &data.detailed_temperature;
+    let number = |value: f64| format!("{:.1}", value);
+
+    Some(vec![
+        forecast_date(forecast.timestamp, utc_offset)?.to_string(),
+        condition_name(&data.condition).to_string(),
+        data.description.clone(),
+        number(temperature.min),
+        number(temperature.max),
+        number(temperature.morning),
+        number(temperature.day),
+        number(temperature.evening),
+        number(temperature.night),
+        format!("{:.0}", data.precipitation.probability * 100.0),
+        number(data.precipitation.rain_volume),
+        number(data.precipitation.snow_volume),
+        number(data.uv_index),
+        format!("{:.0}", data.humidity),
+        format!("{:.0}", data.pressure),
+        number(data.wind_speed),
+        data.wind_gust.map(number).unwrap_or_default(),
+        format!("{:.0}", data.wind_direction),
+        format!("{:.0}", data.cloud_cover),
+        data.sunrise.and_then(local_time).unwrap_or_default(),
+        data.sunset.and_then(local_time).unwrap_or_default(),
+    ])
+}
+
+fn to_csv(city: &CityWeatherData) -> String {
+    let header = CSV_HEADER.map(String::from).to_vec();
+    std::iter::once(header)
+        .chain(
+            city.weather_data
+                .forecast_data
+                .iter()
+                .filter_map(|forecast| csv_row(forecast, city.weather_data.utc_offset)),
+        )
+        .map(|row| {
+            let fields: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+            format!("{}\r\n", fields.join(","))
+        })
+        .collect()
+}
+
+// E.g. "☀ 19°/12°, 10% rain", the temperatures in the unit of the settings.
+fn day_summary(data: &DayWeatherData, units: &Units) -> String {
+    let temperature = &data.detailed_temperature;
+    let precipitation = &data.precipitation;
+    let summary = format!(
+        "{:.0}°/{:.0}°, {:.0}% {}",
+        units.temperature.convert(temperature.max),
+        units.temperature.convert(temperature.min),
+        precipitation.probability * 100.0,
+        current_locale().precipitation_label(precipitation.snow_volume > precipitation.rain_volume)
+    );
+
+    match condition_symbol(&data.condition) {
+        "" => summary,
+        symbol => format!("{} {}", symbol, summary),
+    }
+}
+
+fn escape_calendar_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+// Long lines are continued on the next line starting with a space, without splitting characters.
+fn fold_calendar_line(line: &str) -> String {
+    let mut folded = String::new();
+    let mut line_length = 0;
+    for c in line.chars() {
+        if line_length + c.len_utf8() > CALENDAR_LINE_LENGTH {
+            folded.push_str("\r\n ");
+            line_length = 1;
+        }
+        folded.push(c);
+        line_length += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+// One all-day event per forecast day. The event ids depend only on the place and the day,
+// so importing a newer export again updates the events instead of duplicating them.
+fn to_calendar(city: &CityWeatherData, units: &Units, now: DateTime<Utc>) -> String {
+    let city_data = &city.city_data;
+    let stamp = city
+        .fetched_at
+        .and_then(|fetched_at| DateTime::from_timestamp(fetched_at, 0))
+        .unwrap_or(now)
+        .format("%Y%m%dT%H%M%SZ")
+        .to_string();
+
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//Rusty Weather//Forecast//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        format!(
+            "X-WR-CALNAME:{}",
+            escape_calendar_text(&city_data.city_name)
+        ),
+    ];
+
+    for forecast in &city.weather_data.forecast_data {
+        let Some(date) = forecast_date(forecast.timestamp, city.weather_data.utc_offset) else {
+            continue;
+        };
+        let data = &forecast.weather_data;
+        let description = format!(
+            "{}\nWind {:.0} {}, humidity {:.0}%, UV {:.0}",
+            data.description,
+            units.wind_speed.convert(data.wind_speed),
+            units.wind_speed.label(),
+            data.humidity,
+            data.uv_index
+        );
+
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            format!(
+                "UID:{}-{:.4}-{:.4}@rusty-weather",
+                date.format("%Y%m%d"),
+                city_data.lat,
+                city_data.lon
+            ),
+            format!("DTSTAMP:{}", stamp),
+            format!("DTSTART;VALUE=DATE:{}", date.format("%Y%m%d")),
+            format!(
+                "DTEND;VALUE=DATE:{}",
+                date.succ_opt().unwrap_or(date).format("%Y%m%d")
+            ),
+            format!(
+                "SUMMARY:{}",
+                escape_calendar_text(&day_summary(data, units))
+            ),
+            format!("DESCRIPTION:{}", escape_calendar_text(&description)),
+            format!("LOCATION:{}", escape_calendar_text(&city_data.city_name)),
+            format!("GEO:{:.4};{:.4}", city_data.lat, city_data.lon),
+            // the forecast does not block the time in the calendar
+            "TRANSP:TRANSPARENT".to_string(),
+            "END:VEVENT".to_string(),
+        ]);
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|line| fold_calendar_line(line)).collect()
+}
+
+// The forecast of the city in the format. CSV and JSON are always metric,
+// the calendar summaries use the units of the settings.
+pub fn export_weather(
+    city: &CityWeatherData,
+    format: ExportFormat,
+    units: &Units,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    match format {
+        ExportFormat::Csv => Ok(to_csv(city)),
+        ExportFormat::Json => to_json(city),
+        ExportFormat::Calendar => Ok(to_calendar(city, units, Utc::now())),
+    }
+}
+
+// E.g. "zurich-forecast.ics".
+pub fn export_file_name(city: &CityWeatherData, format: ExportFormat) -> String {
+    let name = deunicode::deunicode(&city.city_data.city_name).to_lowercase();
+    let words: Vec<&str> = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+    let name = if words.is_empty() {
+        "city".to_string()
+    } else {
+        words.join("-")
+    };
+    format!("{}-forecast.{}", name, format.extension())
+}
+
+// Saves the export in the download directory, or offers it as a download in the browser.
+// Returns the path or the name of the file.
+pub fn save_export(
+    city: &CityWeatherData,
+    format: ExportFormat,
+    units: &Units,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    save_file(
+        &export_file_name(city, format),
+        format.mime_type(),
+        &export_weather(city, format, units)?,
+    )
+}
+
+// Writes the file in the download directory and returns its path.
+#[cfg(all(not(target_arch = "wasm32"), not(target_os = "android")))]
+pub fn save_file(
+    file_name: &str,
+    _mime_type: &str,
+    data: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let dir = crate::weather::utils::export_dir().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::NotFound, "No directory to export to")
+    })?;
+    std::fs::create_dir_all(&dir)?;
+
+    let path = dir.join(file_name);
+    std::fs::write(&path, data)?;
+    Ok(path.display().to_string())
+}
+
+// Saves the file in the shared Download directory, so other applications can open it, and returns
+// its location.
+#[cfg(target_os = "android")]
+pub fn save_file(
+    file_name: &str,
+    mime_type: &str,
+    data: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    crate::weather::androidstorage::save_download(file_name, mime_type, data)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn js_error(error: wasm_bindgen::JsValue) -> Box<dyn std::error::Error + Send + Sync> {
+    Box::new(std::io::Error::other(format!("{:?}", error)))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn document() -> Result<web_sys::Document, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "No document"))?)
+}
+
+// Offers the file as a download in the browser and returns its name.
+#[cfg(target_arch = "wasm32")]
+pub fn save_file(
+    file_name: &str,
+    mime_type: &str,
+    data: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    use wasm_bindgen::JsCast;
+
+    let document = document()?;
+
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type(mime_type);
+    let parts = js_sys::Array::of1(&wasm_bindgen::JsValue::from_str(data));
+    let blob =
+        web_sys::Blob::new_with_str_sequence_and_options(&parts, &options).map_err(js_error)?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob).map_err(js_error)?;
+
+    let anchor: web_sys::HtmlAnchorElement = document
+        .create_element("a")
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(|element: web_sys::Element| js_error(element.into()))?;
+    anchor.set_href(&url);
+    anchor.set_download(file_name);
+    anchor.click();
+
+    web_sys::Url::revoke_object_url(&url).map_err(js_error)?;
+    Ok(file_name.to_string())
+}
+
+// Asks the user for a file in the browser and passes its content to on_load.
+// Nothing is called when the user closes the dialog without choosing a file.
+#[cfg(target_arch = "wasm32")]
+pub fn open_file(
+    accept: &str,
+    on_load: impl FnOnce(Result<String, Box<dyn std::error::Error + Send + Sync>>) + 'static,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use wasm_bindgen::JsCast;
+
+    let input: web_sys::HtmlInputElement = document()?
+        .create_element("input")
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(|element: web_sys::Element| js_error(element.into()))?;
+    input.set_type("file");
+    input.set_accept(accept);
+
+    let on_change = wasm_bindgen::closure::Closure::once_into_js({
+        let input = input.clone();
+        move || {
+            let Some(file) = input.files().and_then(|files| files.get(0)) else {
+                return;
+            };
+            wasm_bindgen_futures::spawn_local(async move {
+                let text = wasm_bindgen_futures::JsFuture::from(file.text()).await;
+                on_load(
+                    text.map(|text| text.as_string().unwrap_or_default())
+                        .map_err(js_error),
+                );
+            });
+        }
+    });
+    input.set_onchange(Some(on_change.unchecked_ref()));
+    input.click();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::weather::weathercontroller::{
+        CityData, PrecipitationData, RefreshStatus, TemperatureData, WeatherData,
+    };
+
+    // noon UTC, the same day in the local time zone of the test machines
+    const DAY_TIMESTAMP: i64 = 1_714_564_800; // 2024-05-01
+
+    fn city_weather(name: &str) -> CityWeatherData {
+        let day = |offset: i64, condition, max, min, probability| ForecastWeatherData {
+            timestamp: DAY_TIMESTAMP + offset * 86400,
+            weather_data: DayWeatherData {
+                condition,
+                description: "clear sky".to_string(),
+                detailed_temperature: TemperatureData {
+                    min,
+                    max,
+                    ..Default::default()
+                },
+                precipitation: PrecipitationData {
+                    probability,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        };
+
+        CityWeatherData {
+            city_data: CityData {
+                lat: 47.3769,
+                lon: 8.5417,
+                city_name: name.to_string(),
+                label: None,
+                note: None,
+            },
+            weather_data: WeatherData {
+                forecast_data: vec![
+                    day(0, WeatherCondition::Sunny, 19.4, 11.6, 0.1),
+                    day(1, WeatherCondition::Rainy, 14.0, 9.0, 0.85),
+                ],
+                ..Default::default()
+            },
+            alerts: vec![],
+            refresh_status: RefreshStatus::Fresh,
+            fetched_at: Some(DAY_TIMESTAMP),
+            observed_at: None,
+        }
+    }
+
+    #[test]
+    fn csv_has_a_row_per_day() {
+        let csv = to_csv(&city_weather("Zürich"));
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("date,condition,description,temperature_min_c,"));
+        assert!(lines[1].starts_with("2024-05-01,sunny,clear sky,11.6,19.4,"));
+        assert!(lines[2].starts_with("2024-05-02,rainy,"));
+        assert_eq!(csv_field("light rain, windy"), "\"light rain, windy\"");
+    }
+
+    #[test]
+    fn days_are_dated_in_the_city() {
+        let mut city = city_weather("Auckland");
+        // noon UTC is already the next day in New Zealand
+        city.weather_data.utc_offset = Some(13 * 3600);
+
+        let csv = to_csv(&city);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines[1].starts_with("2024-05-02,sunny,"));
+
+        let json: serde_json::Value = serde_json::from_str(&to_json(&city).unwrap()).unwrap();
+        assert_eq!(json["days"][0]["date"], "2024-05-02");
+        assert_eq!(json["fetched_at"], "2024-05-02T01:00:00+13:00");
+    }
+
+    #[test]
+    fn json_follows_the_schema() {
+        let json: serde_json::Value =
+            serde_json::from_str(&to_json(&city_weather("Zürich")).unwrap()).unwrap();
+
+        assert_eq!(json["format"], "rusty-weather-forecast");
+        assert_eq!(json["version"], 1);
+        assert_eq!(json["city"]["name"], "Zürich");
+        assert_eq!(json["days"][0]["date"], "2024-05-01");
+        assert_eq!(json["days"][1]["condition"], "rainy");
+        assert_eq!(json["days"][1]["precipitation"]["probability"], 85.0);
+        assert!(json["days"][0]["sunrise"].is_null());
+    }
+
+    #[test]
+    fn calendar_has_an_event_per_day() {
+        let calendar = to_calendar(&city_weather("Zürich"), &Units::default(), Utc::now());
+
+        assert!(calendar.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(calendar.ends_with("END:VCALENDAR\r\n"));
+        assert_eq!(calendar.matches("BEGIN:VEVENT").count(), 2);
+        assert!(calendar.contains("DTSTART;VALUE=DATE:20240501\r\nDTEND;VALUE=DATE:20240502\r\n"));
+        assert!(calendar.contains("SUMMARY:☀ 19°/12°\\, 10% rain\r\n"));
+        assert!(calendar.contains("UID:20240502-47.3769-8.5417@rusty-weather\r\n"));
+    }
+
+    #[test]
+    fn long_calendar_lines_are_folded() {
+        let line = format!("DESCRIPTION:{}", "ü".repeat(50));
+        let folded = fold_calendar_line(&line);
+
+        assert!(folded
+            .split("\r\n")
+            .all(|line| line.len() <= CALENDAR_LINE_LENGTH));
+        assert_eq!(folded.replace("\r\n ", ""), format!("{}\r\n", line));
+    }
+
+    #[test]
+    fn file_name_is_ascii() {
+        let city = city_weather("São Paulo");
+        assert_eq!(
+            export_file_name(&city, ExportFormat::Calendar),
+            "sao-paulo-forecast.ics"
+        );
+    }
+}
@@ -0,0 +1,303 @@
+use std::io::Read;
+use std::sync::OnceLock;
+
+use crate::weather::weathercontroller::GeoLocationData;
+
+// Number of results returned by the location search.
+pub const SEARCH_RESULTS_LIMIT: usize = 5;
+
+// Places further away are not used to name the coordinates.
+const REVERSE_GEOCODING_MAX_DISTANCE_KM: f64 = 30.0;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+// Excerpt of the GeoNames cities15000 extract with the largest cities, the full list is generated
+// with scripts/update_gazetteer.py.
+const BUNDLED_DATA: &[u8] = std::include_bytes!("./gazetteer.tsv.gz");
+
+// Queries shorter than this are matched only exactly or by prefix.
+const FUZZY_MIN_QUERY_LENGTH: usize = 4;
+
+// Match quality, the better matches come first.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+enum MatchKind {
+    Exact,
+    Prefix,
+    WordPrefix,
+    Fuzzy,
+}
+
+struct GazetteerEntry {
+    location: GeoLocationData,
+    population: u64,
+    // IANA time zone, e.g. "Europe/Berlin"
+    timezone: String,
+    // normalized name and alternate names
+    search_names: Vec<String>,
+}
+
+// Embedded list of the larger cities, searched without any geocoding api.
+pub struct Gazetteer {
+    entries: Vec<GazetteerEntry>,
+}
+
+// Lowercase ASCII without diacritics and punctuation, e.g. "Zürich" -> "zurich".
+fn normalize(text: &str) -> String {
+    deunicode::deunicode(text)
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+// Great-circle distance in kilometers.
+fn distance_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let half_lat = (lat2 - lat1) / 2.0;
+    let half_lon = (lon2 - lon1).to_radians() / 2.0;
+    let a = half_lat.sin().powi(2) + lat1.cos() * lat2.cos() * half_lon.sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+// Levenshtein distance, the strings are short so the simple variant is enough.
+fn edit_distance(a: &[u8], b: &[u8]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn match_name(name: &str, query: &str) -> Option<MatchKind> {
+    if name == query {
+        return Some(MatchKind::Exact);
+    }
+    if name.starts_with(query) {
+        return Some(MatchKind::Prefix);
+    }
+    if name.split(' ').skip(1).any(|word| word.starts_with(query)) {
+        return Some(MatchKind::WordPrefix);
+    }
+
+    // typos are tolerated in the part of the name typed so far
+    if query.len() >= FUZZY_MIN_QUERY_LENGTH {
+        let max_distance = if query.len() >= 8 { 2 } else { 1 };
+        let name = name.as_bytes();
+        let prefix = &name[..name.len().min(query.len())];
+        if edit_distance(prefix, query.as_bytes()) <= max_distance {
+            return Some(MatchKind::Fuzzy);
+        }
+    }
+
+    None
+}
+
+impl GazetteerEntry {
+    // One tab separated line: name, alternate names, country, admin1, latitude, longitude,
+    // population, timezone.
+    fn parse(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [name, alternate_names, country, admin1, lat, lon, population, timezone] = fields[..]
+        else {
+            return None;
+        };
+
+        let search_names = std::iter::once(name)
+            .chain(alternate_names.split(','))
+            .map(normalize)
+            .filter(|search_name| !search_name.is_empty())
+            .collect();
+
+        Some(Self {
+            location: GeoLocationData {
+                name: name.to_string(),
+                lat: lat.parse().ok()?,
+                lon: lon.parse().ok()?,
+                country: country.to_string(),
+                state: (!admin1.is_empty()).then(|| admin1.to_string()),
+                timezone: None,
+            },
+            population: population.parse().ok()?,
+            timezone: timezone.to_string(),
+            search_names,
+        })
+    }
+
+    // The location found by the search, with the time zone of the city.
+    fn location(&self) -> GeoLocationData {
+        GeoLocationData {
+            timezone: (!self.timezone.is_empty()).then(|| self.timezone.clone()),
+            ..self.location.clone()
+        }
+    }
+
+    fn match_query(&self, query: &str) -> Option<MatchKind> {
+        self.search_names
+            .iter()
+            .filter_map(|search_name| match_name(search_name, query))
+            .min()
+    }
+}
+
+impl Gazetteer {
+    pub fn parse(data: &str) -> Self {
+        let entries = data
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let entry = GazetteerEntry::parse(line);
+                if entry.is_none() {
+                    log::warn!("Gazetteer line skipped, it cannot be parsed: {}", line);
+                }
+                entry
+            })
+            .collect();
+
+        Self { entries }
+    }
+
+    // The cities bundled with the application, decompressed and parsed on first use.
+    pub fn bundled() -> &'static Self {
+        static GAZETTEER: OnceLock<Gazetteer> = OnceLock::new();
+        GAZETTEER.get_or_init(|| {
+            let mut data = String::new();
+            if let Err(error) = flate2::read::GzDecoder::new(BUNDLED_DATA).read_to_string(&mut data)
+            {
+                log::error!("Bundled city list cannot be read: {}", error);
+                data.clear();
+            }
+            Self::parse(&data)
+        })
+    }
+
+    // Matches the names and the alternate names by prefix, tolerates diacritics and small typos.
+    // The better matches come first, the larger cities first among equally good matches.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<GeoLocationData> {
+        let query = normalize(query);
+        if query.is_empty() {
+            return vec![];
+        }
+
+        let mut matches: Vec<(MatchKind, &GazetteerEntry)> = self
+            .entries
+            .iter()
+            .filter_map(|entry| entry.match_query(&query).map(|kind| (kind, entry)))
+            .collect();
+        matches.sort_by_key(|(kind, entry)| (*kind, std::cmp::Reverse(entry.population)));
+
+        matches
+            .into_iter()
+            .take(limit)
+            .map(|(_, entry)| entry.location())
+            .collect()
+    }
+
+    // The closest place within the given distance.
+    pub fn nearest(&self, lat: f64, lon: f64, max_distance_km: f64) -> Option<GeoLocationData> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let location = &entry.location;
+                (distance_km(lat, lon, location.lat, location.lon), entry)
+            })
+            .filter(|(distance, _)| *distance <= max_distance_km)
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, entry)| entry.location())
+    }
+}
+
+// Loads the bundled cities when the application starts, so the first search does not wait for
+// them. On wasm there are no threads, the cities are loaded right away.
+pub fn load_bundled() {
+    #[cfg(not(target_arch = "wasm32"))]
+    std::thread::spawn(|| {
+        Gazetteer::bundled();
+    });
+
+    #[cfg(target_arch = "wasm32")]
+    Gazetteer::bundled();
+}
+
+// Offline location search, usable with any weather provider.
+pub fn search_location(query: &str) -> Vec<GeoLocationData> {
+    Gazetteer::bundled().search(query, SEARCH_RESULTS_LIMIT)
+}
+
+// Offline reverse geocoding, the bundled city closest to the coordinates.
+pub fn reverse_geocode(lat: f64, lon: f64) -> Option<GeoLocationData> {
+    Gazetteer::bundled().nearest(lat, lon, REVERSE_GEOCODING_MAX_DISTANCE_KM)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn city_names(locations: &[GeoLocationData]) -> Vec<&str> {
+        locations
+            .iter()
+            .map(|location| location.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn bundled_data_is_parsed() {
+        let gazetteer = Gazetteer::bundled();
+        assert!(gazetteer.entries.len() >= 250);
+
+        let berlin = &gazetteer.search("Berlin", 1)[0];
+        assert_eq!(berlin.country, "DE");
+        assert_eq!(berlin.state.as_deref(), Some("Berlin"));
+        assert_eq!(berlin.timezone.as_deref(), Some("Europe/Berlin"));
+    }
+
+    #[test]
+    fn diacritics_are_ignored() {
+        let gazetteer = Gazetteer::bundled();
+        assert_eq!(city_names(&gazetteer.search("Zurich", 5)), ["Zürich"]);
+        assert_eq!(city_names(&gazetteer.search("malmö", 5)), ["Malmö"]);
+        assert_eq!(city_names(&gazetteer.search("Sao Pa", 5)), ["São Paulo"]);
+    }
+
+    #[test]
+    fn results_are_ranked() {
+        let gazetteer = Gazetteer::parse(
+            "Springfield\t\tUS\tIllinois\t39.8\t-89.64\t116250\tAmerica/Chicago\n\
+             Springfield\t\tUS\tMissouri\t37.22\t-93.3\t169176\tAmerica/Chicago\n\
+             West Springfield\t\tUS\tMassachusetts\t42.1\t-72.62\t28835\tAmerica/New_York\n\
+             Spring\t\tUS\tTexas\t30.08\t-95.42\t62559\tAmerica/Chicago\n",
+        );
+
+        let results = gazetteer.search("spring", 5);
+        let states: Vec<&str> = results
+            .iter()
+            .map(|location| location.state.as_deref().unwrap())
+            .collect();
+        assert_eq!(states, ["Texas", "Missouri", "Illinois", "Massachusetts"]);
+    }
+
+    #[test]
+    fn alternate_names_and_typos_are_matched() {
+        let gazetteer = Gazetteer::bundled();
+        assert_eq!(city_names(&gazetteer.search("Wien", 1)), ["Vienna"]);
+        assert_eq!(city_names(&gazetteer.search("Munchen", 1)), ["Munich"]);
+        assert_eq!(city_names(&gazetteer.search("Barcleona", 1)), ["Barcelona"]);
+        assert!(gazetteer.search("xyzzy", 5).is_empty());
+        assert!(gazetteer.search(" , ", 5).is_empty());
+    }
+
+    #[test]
+    fn nearest_city_is_found() {
+        // Potsdamer Platz
+        let location = reverse_geocode(52.5096, 13.3760).unwrap();
+        assert_eq!(location.name, "Berlin");
+
+        // in the middle of the Atlantic
+        assert!(reverse_geocode(30.0, -40.0).is_none());
+    }
+}
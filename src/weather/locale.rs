@@ -0,0 +1,207 @@
+use chrono::Weekday;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+
+// Locales with translated UI strings (see the lang directory).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Locale {
+    #[default]
+    English,
+    German,
+    Russian,
+}
+
+static CURRENT_LOCALE: RwLock<Locale> = RwLock::new(Locale::English);
+
+pub fn current_locale() -> Locale {
+    *CURRENT_LOCALE.read().unwrap()
+}
+
+pub fn set_current_locale(locale: Locale) {
+    *CURRENT_LOCALE.write().unwrap() = locale;
+}
+
+// BCP 47 tag of the default locale of the Java VM, e.g. "de-DE".
+#[cfg(target_os = "android")]
+fn android_language_tag() -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let context = ndk_context::android_context();
+    let vm = unsafe { jni::JavaVM::from_raw(context.vm().cast()) }?;
+    let mut env = vm.attach_current_thread()?;
+
+    let locale = env
+        .call_static_method(
+            "java/util/Locale",
+            "getDefault",
+            "()Ljava/util/Locale;",
+            &[],
+        )?
+        .l()?;
+    let tag = env
+        .call_method(&locale, "toLanguageTag", "()Ljava/lang/String;", &[])?
+        .l()?;
+    let tag: String = env.get_string(&tag.into())?.into();
+    Ok(tag)
+}
+
+impl Locale {
+    // Accepts POSIX (de_DE.UTF-8) and BCP 47 (de-DE) language tags.
+    pub fn from_language_tag(tag: &str) -> Option<Self> {
+        let language = tag.split(['_', '-', '.', '@']).next()?.to_lowercase();
+        match language.as_str() {
+            "en" => Some(Locale::English),
+            "de" => Some(Locale::German),
+            "ru" => Some(Locale::Russian),
+            _ => None,
+        }
+    }
+
+    pub fn system() -> Self {
+        // the environment of an application has no locale on Android
+        #[cfg(target_os = "android")]
+        match android_language_tag() {
+            Ok(tag) => {
+                if let Some(locale) = Self::from_language_tag(&tag) {
+                    return locale;
+                }
+            }
+            Err(e) => log::warn!("Failed to read the system language: {}", e),
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        if let Some(locale) = web_sys::window()
+            .and_then(|window| window.navigator().language())
+            .and_then(|tag| Self::from_language_tag(&tag))
+        {
+            return locale;
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            for variable in ["LANGUAGE", "LC_ALL", "LC_MESSAGES", "LANG"] {
+                if let Ok(value) = std::env::var(variable) {
+                    // LANGUAGE can contain a list of languages separated by colons
+                    if let Some(locale) = value.split(':').find_map(Self::from_language_tag) {
+                        return locale;
+                    }
+                }
+            }
+        }
+
+        Locale::default()
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Locale::English => "en",
+            Locale::German => "de",
+            Locale::Russian => "ru",
+        }
+    }
+
+    pub fn today_label(&self) -> &'static str {
+        match self {
+            Locale::English => "Today",
+            Locale::German => "Heute",
+            Locale::Russian => "Сегодня",
+        }
+    }
+
+    // Precipitation word of the exported forecast summaries.
+    pub fn precipitation_label(&self, snow: bool) -> &'static str {
+        match (self, snow) {
+            (Locale::English, false) => "rain",
+            (Locale::English, true) => "snow",
+            (Locale::German, false) => "Regen",
+            (Locale::German, true) => "Schnee",
+            (Locale::Russian, false) => "дождь",
+            (Locale::Russian, true) => "снег",
+        }
+    }
+
+    // Description of a WMO weather code, for the providers reporting only the code.
+    #[cfg_attr(not(feature = "open_meteo"), allow(dead_code))]
+    pub fn weather_code_description(&self, weather_code: u8) -> &'static str {
+        let index = match weather_code {
+            0 => 0,
+            1 => 1,
+            2 => 2,
+            3 => 3,
+            45 | 48 => 4,
+            51..=55 => 5,
+            56 | 57 => 6,
+            61..=65 => 7,
+            66 | 67 => 8,
+            71..=75 => 9,
+            77 => 10,
+            80..=82 => 11,
+            85 | 86 => 12,
+            95 => 13,
+            96..=99 => 14,
+            _ => return "",
+        };
+
+        let descriptions = match self {
+            Locale::English => [
+                "clear sky",
+                "mainly clear",
+                "partly cloudy",
+                "overcast",
+                "fog",
+                "drizzle",
+                "freezing drizzle",
+                "rain",
+                "freezing rain",
+                "snow",
+                "snow grains",
+                "rain showers",
+                "snow showers",
+                "thunderstorm",
+                "thunderstorm with hail",
+            ],
+            Locale::German => [
+                "klarer Himmel",
+                "überwiegend klar",
+                "teilweise bewölkt",
+                "bedeckt",
+                "Nebel",
+                "Nieselregen",
+                "gefrierender Nieselregen",
+                "Regen",
+                "gefrierender Regen",
+                "Schnee",
+                "Schneegriesel",
+                "Regenschauer",
+                "Schneeschauer",
+                "Gewitter",
+                "Gewitter mit Hagel",
+            ],
+            Locale::Russian => [
+                "ясно",
+                "преимущественно ясно",
+                "переменная облачность",
+                "пасмурно",
+                "туман",
+                "морось",
+                "ледяная морось",
+                "дождь",
+                "ледяной дождь",
+                "снег",
+                "снежная крупа",
+                "ливень",
+                "снегопад",
+                "гроза",
+                "гроза с градом",
+            ],
+        };
+        descriptions[index]
+    }
+
+    pub fn short_weekday_name(&self, weekday: Weekday) -> &'static str {
+        let names = match self {
+            Locale::English => ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"],
+            Locale::German => ["Mo", "Di", "Mi", "Do", "Fr", "Sa", "So"],
+            Locale::Russian => ["Пн", "Вт", "Ср", "Чт", "Пт", "Сб", "Вс"],
+        };
+        names[weekday.num_days_from_monday() as usize]
+    }
+}
@@ -1,3 +1,10 @@
+#[cfg(target_os = "android")]
+mod androidstorage;
+mod citystorage;
+mod refreshscheduler;
+mod statestore;
+mod syntheticweather;
+mod undo;
 mod weathercontroller;
 mod weatherdisplaycontroller;
 
@@ -5,8 +12,14 @@ mod dummyweathercontroller;
 
 pub use weathercontroller::WeatherControllerPointer;
 pub use weathercontroller::WeatherControllerSharedPointer;
+pub use weathercontroller::{CityData, CityWeatherData, LoadStatus, RefreshStatus};
 pub use weatherdisplaycontroller::WeatherDisplayController;
 
+pub use refreshscheduler::DEFAULT_CACHE_TTL_MINUTES;
+
+#[cfg(all(test, feature = "open_meteo"))]
+pub use statestore::MemoryStateStore;
+
 pub use dummyweathercontroller::DummyWeatherController;
 
 #[cfg(all(not(target_arch = "wasm32"), feature = "open_weather"))]
@@ -15,4 +28,24 @@ mod openweathercontroller;
 #[cfg(all(not(target_arch = "wasm32"), feature = "open_weather"))]
 pub use openweathercontroller::OpenWeatherController;
 
+#[cfg(feature = "open_meteo")]
+mod openmeteocontroller;
+
+#[cfg(feature = "open_meteo")]
+pub use openmeteocontroller::OpenMeteoController;
+
+#[cfg(all(
+    test,
+    not(target_arch = "wasm32"),
+    any(feature = "open_weather", feature = "open_meteo")
+))]
+mod stubserver;
+
+pub mod citylist;
+pub mod coordinates;
+pub mod export;
+pub mod gazetteer;
+pub mod locale;
+pub mod runtime;
+pub mod units;
 pub mod utils;
@@ -0,0 +1,1061 @@
+#![cfg(feature = "open_meteo")]
+
+use async_trait::async_trait;
+use chrono::Duration;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::vec;
+
+use crate::weather::citystorage::{
+    migrate_legacy_key, read_stored_cities, write_stored_cities, StoredCity, StoredWeather,
+    CITIES_STORED_KEY,
+};
+use crate::weather::locale::current_locale;
+use crate::weather::statestore::{default_state_store, StateStorePointer};
+use crate::weather::undo::UndoBuffer;
+use crate::weather::weathercontroller::{
+    index_out_of_bounds, move_element, remove_element, CityData, CityWeatherData, DayWeatherData,
+    ForecastWeatherData, GeoLocationData, HourlyWeatherData, LoadStatus, PrecipitationData,
+    RefreshStatus, TemperatureData, WeatherCondition, WeatherController, WeatherData,
+    DEFAULT_REFRESH_CONCURRENCY,
+};
+
+const FORECAST_URL: &str = "https://api.open-meteo.com/v1/forecast";
+const GEOCODING_URL: &str = "https://geocoding-api.open-meteo.com/v1/search";
+
+const FORECAST_DAYS: usize = 8;
+const HOURLY_FORECAST_LENGTH: usize = 48;
+const SEARCH_RESULTS_COUNT: usize = 10;
+// name of the provider of the stored weather
+const PROVIDER_NAME: &str = "open_meteo";
+// key of the cities stored by the previous versions
+const LEGACY_CITIES_STORED_KEY: &str = "open_meteo_cities_data.json";
+
+const CURRENT_VARIABLES: &str = "temperature_2m,relative_humidity_2m,apparent_temperature,rain,\
+    snowfall,weather_code,cloud_cover,pressure_msl,wind_speed_10m,wind_direction_10m,wind_gusts_10m";
+const HOURLY_VARIABLES: &str = "temperature_2m,relative_humidity_2m,dew_point_2m,\
+    precipitation_probability,rain,snowfall,weather_code,pressure_msl,cloud_cover,visibility,uv_index";
+const DAILY_VARIABLES: &str = "weather_code,temperature_2m_max,temperature_2m_min,\
+    apparent_temperature_max,sunrise,sunset,uv_index_max,precipitation_probability_max,rain_sum,\
+    snowfall_sum,wind_speed_10m_max,wind_gusts_10m_max,wind_direction_10m_dominant";
+
+// Open-Meteo reports snowfall in centimeters
+const SNOWFALL_CM_TO_MM: f64 = 10.0;
+
+// hours of the day used for the detailed temperatures
+const MORNING_HOUR: i64 = 6;
+const DAY_HOUR: i64 = 12;
+const EVENING_HOUR: i64 = 18;
+const NIGHT_HOUR: i64 = 0;
+
+// The responses are requested with unix timestamps, metric units and wind speed in m/s.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CurrentResponse {
+    pub time: i64,
+    pub interval: i64, // seconds
+    pub temperature_2m: f64,
+    pub relative_humidity_2m: f64,
+    pub apparent_temperature: f64,
+    pub rain: f64,     // mm in the preceding interval
+    pub snowfall: f64, // cm in the preceding interval
+    pub weather_code: u8,
+    pub cloud_cover: f64,
+    pub pressure_msl: f64,
+    pub wind_speed_10m: f64,
+    pub wind_direction_10m: f64,
+    pub wind_gusts_10m: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HourlyResponse {
+    pub time: Vec<i64>,
+    pub temperature_2m: Vec<Option<f64>>,
+    pub relative_humidity_2m: Vec<Option<f64>>,
+    pub dew_point_2m: Vec<Option<f64>>,
+    pub precipitation_probability: Vec<Option<f64>>, // %
+    pub rain: Vec<Option<f64>>,
+    pub snowfall: Vec<Option<f64>>, // cm
+    pub weather_code: Vec<Option<u8>>,
+    pub pressure_msl: Vec<Option<f64>>,
+    pub cloud_cover: Vec<Option<f64>>,
+    pub visibility: Vec<Option<f64>>,
+    pub uv_index: Vec<Option<f64>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DailyResponse {
+    pub time: Vec<i64>, // local midnight
+    pub weather_code: Vec<Option<u8>>,
+    pub temperature_2m_max: Vec<Option<f64>>,
+    pub temperature_2m_min: Vec<Option<f64>>,
+    pub apparent_temperature_max: Vec<Option<f64>>,
+    pub sunrise: Vec<Option<i64>>,
+    pub sunset: Vec<Option<i64>>,
+    pub uv_index_max: Vec<Option<f64>>,
+    pub precipitation_probability_max: Vec<Option<f64>>, // %
+    pub rain_sum: Vec<Option<f64>>,
+    pub snowfall_sum: Vec<Option<f64>>, // cm
+    pub wind_speed_10m_max: Vec<Option<f64>>,
+    pub wind_gusts_10m_max: Vec<Option<f64>>,
+    pub wind_direction_10m_dominant: Vec<Option<f64>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ForecastResponse {
+    // offset of the time zone of the location, requested with timezone=auto
+    #[serde(default)]
+    pub utc_offset_seconds: Option<i32>,
+    pub current: Option<CurrentResponse>,
+    pub hourly: Option<HourlyResponse>,
+    pub daily: Option<DailyResponse>,
+}
+
+#[derive(Deserialize, Debug)]
+struct GeocodingResponse {
+    // missing when nothing was found
+    #[serde(default)]
+    results: Vec<GeocodingResult>,
+}
+
+#[derive(Deserialize, Debug)]
+struct GeocodingResult {
+    name: String,
+    latitude: f64,
+    longitude: f64,
+    country_code: Option<String>,
+    admin1: Option<String>,
+    timezone: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct OpenMeteoApi {
+    client: reqwest::Client,
+    forecast_url: String,
+    geocoding_url: String,
+}
+
+#[derive(Clone)]
+pub struct OpenMeteoClient {
+    pub city_data: CityData,
+    pub refresh_status: RefreshStatus,
+    pub fetched_at: Option<i64>,
+    pub weather: Option<StoredWeather>,
+}
+
+pub struct OpenMeteoController {
+    weather_api: OpenMeteoApi,
+    city_clients: Mutex<Vec<OpenMeteoClient>>,
+    removed_clients: Mutex<UndoBuffer<OpenMeteoClient>>,
+    state_store: StateStorePointer,
+    refresh_concurrency: usize,
+}
+
+impl OpenMeteoController {
+    pub fn new() -> Self {
+        Self::with_base_urls(FORECAST_URL, GEOCODING_URL)
+    }
+
+    pub fn with_base_urls(forecast_url: &str, geocoding_url: &str) -> Self {
+        Self {
+            weather_api: OpenMeteoApi {
+                client: reqwest::Client::new(),
+                forecast_url: forecast_url.to_string(),
+                geocoding_url: geocoding_url.to_string(),
+            },
+            city_clients: Mutex::new(vec![]),
+            removed_clients: Mutex::new(UndoBuffer::new()),
+            state_store: default_state_store(),
+            refresh_concurrency: DEFAULT_REFRESH_CONCURRENCY,
+        }
+    }
+
+    pub fn set_refresh_concurrency(&mut self, limit: usize) {
+        self.refresh_concurrency = limit.max(1);
+    }
+
+    #[cfg(test)]
+    pub fn set_state_store(&mut self, state_store: StateStorePointer) {
+        self.state_store = state_store;
+    }
+
+    // See https://open-meteo.com/en/docs for the WMO weather interpretation codes.
+    fn weather_condition_from_weather_code(weather_code: u8) -> WeatherCondition {
+        match weather_code {
+            0 => WeatherCondition::Sunny,
+            1 => WeatherCondition::PartiallyCloudy,
+            2 => WeatherCondition::MostlyCloudy,
+            3 => WeatherCondition::Cloudy,
+            45 | 48 => WeatherCondition::Foggy,
+            51..=57 | 61..=67 => WeatherCondition::Rainy,
+            80..=82 => WeatherCondition::SunnyRainy,
+            71..=77 | 85 | 86 => WeatherCondition::Snowy,
+            95..=99 => WeatherCondition::Stormy,
+            _ => WeatherCondition::Unknown,
+        }
+    }
+
+    // Open-Meteo reports only the code, the description is shown in the current language like
+    // the ones requested from OpenWeather.
+    fn description_from_weather_code(weather_code: u8) -> String {
+        current_locale()
+            .weather_code_description(weather_code)
+            .to_string()
+    }
+
+    fn value_at<T: Copy>(values: &[Option<T>], index: usize) -> Option<T> {
+        values.get(index).copied().flatten()
+    }
+
+    // Index of the hour containing the given timestamp.
+    fn hourly_index(hourly: &HourlyResponse, timestamp: i64) -> Option<usize> {
+        hourly.time.iter().rposition(|time| *time <= timestamp)
+    }
+
+    fn hourly_value_at<T: Copy>(
+        hourly: Option<&HourlyResponse>,
+        values: impl Fn(&HourlyResponse) -> &[Option<T>],
+        timestamp: i64,
+    ) -> Option<T> {
+        let hourly = hourly?;
+        Self::value_at(values(hourly), Self::hourly_index(hourly, timestamp)?)
+    }
+
+    fn current_day_weather_data_from_response(
+        weather_response: &Option<ForecastResponse>,
+    ) -> DayWeatherData {
+        if let Some(weather_data) = weather_response {
+            if let Some(current) = &weather_data.current {
+                let today_weather_info =
+                    Self::forecast_day_weather_data_from_response(weather_response)
+                        .into_iter()
+                        .next()
+                        .map(|forecast_data| forecast_data.weather_data);
+                let hourly = weather_data.hourly.as_ref();
+
+                let detailed_temperature = match &today_weather_info {
+                    Some(info) => info.detailed_temperature.clone(),
+                    None => TemperatureData {
+                        min: current.temperature_2m,
+                        max: current.temperature_2m,
+
+                        morning: current.temperature_2m,
+                        day: current.temperature_2m,
+                        evening: current.temperature_2m,
+                        night: current.temperature_2m,
+                    },
+                };
+
+                // scale the amount from the preceding interval to mm/h
+                let hourly_rate = 3600.0 / current.interval.max(1) as f64;
+                let precipitation = PrecipitationData {
+                    probability: today_weather_info
+                        .as_ref()
+                        .map_or(0 as f64, |info| info.precipitation.probability),
+                    rain_volume: current.rain * hourly_rate,
+                    snow_volume: current.snowfall * SNOWFALL_CM_TO_MM * hourly_rate,
+                };
+
+                return DayWeatherData {
+                    description: Self::description_from_weather_code(current.weather_code),
+                    condition: Self::weather_condition_from_weather_code(current.weather_code),
+                    current_temperature: current.temperature_2m,
+                    detailed_temperature,
+                    precipitation,
+                    uv_index: Self::hourly_value_at(hourly, |h| &h.uv_index, current.time)
+                        .unwrap_or_default(),
+
+                    feels_like_temperature: current.apparent_temperature,
+                    humidity: current.relative_humidity_2m,
+                    pressure: current.pressure_msl,
+                    dew_point: Self::hourly_value_at(hourly, |h| &h.dew_point_2m, current.time)
+                        .unwrap_or_default(),
+                    wind_speed: current.wind_speed_10m,
+                    wind_gust: current.wind_gusts_10m,
+                    wind_direction: current.wind_direction_10m,
+                    cloud_cover: current.cloud_cover,
+                    visibility: Self::hourly_value_at(hourly, |h| &h.visibility, current.time),
+                    sunrise: today_weather_info.as_ref().and_then(|info| info.sunrise),
+                    sunset: today_weather_info.as_ref().and_then(|info| info.sunset),
+                };
+            }
+        }
+
+        DayWeatherData::default()
+    }
+
+    fn forecast_day_weather_data_from_response(
+        weather_response: &Option<ForecastResponse>,
+    ) -> Vec<ForecastWeatherData> {
+        let mut forecast_weather_info: Vec<ForecastWeatherData> = vec![];
+
+        if let Some(weather_data) = weather_response {
+            if let Some(daily) = &weather_data.daily {
+                let hourly = weather_data.hourly.as_ref();
+
+                for (index, time) in daily.time.iter().enumerate() {
+                    let daily_value = |values: &[Option<f64>]| Self::value_at(values, index);
+                    let hourly_value = |values: fn(&HourlyResponse) -> &[Option<f64>],
+                                        hour: i64| {
+                        Self::hourly_value_at(hourly, values, time + hour * 3600)
+                    };
+
+                    let weather_code =
+                        Self::value_at(&daily.weather_code, index).unwrap_or(u8::MAX);
+                    let min = daily_value(&daily.temperature_2m_min).unwrap_or_default();
+                    let max = daily_value(&daily.temperature_2m_max).unwrap_or_default();
+                    let temperature_at = |hour: i64| {
+                        hourly_value(|h| &h.temperature_2m, hour).unwrap_or((min + max) / 2.0)
+                    };
+
+                    let detailed_temperature = TemperatureData {
+                        min,
+                        max,
+
+                        morning: temperature_at(MORNING_HOUR),
+                        day: temperature_at(DAY_HOUR),
+                        evening: temperature_at(EVENING_HOUR),
+                        night: temperature_at(NIGHT_HOUR),
+                    };
+
+                    let precipitation = PrecipitationData {
+                        probability: daily_value(&daily.precipitation_probability_max)
+                            .unwrap_or_default()
+                            / 100.0,
+                        rain_volume: daily_value(&daily.rain_sum).unwrap_or_default(),
+                        snow_volume: daily_value(&daily.snowfall_sum).unwrap_or_default()
+                            * SNOWFALL_CM_TO_MM,
+                    };
+
+                    let day_weather_info = DayWeatherData {
+                        description: Self::description_from_weather_code(weather_code),
+                        condition: Self::weather_condition_from_weather_code(weather_code),
+                        current_temperature: detailed_temperature.day,
+                        detailed_temperature,
+                        precipitation,
+                        uv_index: daily_value(&daily.uv_index_max).unwrap_or_default(),
+
+                        feels_like_temperature: daily_value(&daily.apparent_temperature_max)
+                            .unwrap_or_default(),
+                        humidity: hourly_value(|h| &h.relative_humidity_2m, DAY_HOUR)
+                            .unwrap_or_default(),
+                        pressure: hourly_value(|h| &h.pressure_msl, DAY_HOUR).unwrap_or_default(),
+                        dew_point: hourly_value(|h| &h.dew_point_2m, DAY_HOUR).unwrap_or_default(),
+                        wind_speed: daily_value(&daily.wind_speed_10m_max).unwrap_or_default(),
+                        wind_gust: daily_value(&daily.wind_gusts_10m_max),
+                        wind_direction: daily_value(&daily.wind_direction_10m_dominant)
+                            .unwrap_or_default(),
+                        cloud_cover: hourly_value(|h| &h.cloud_cover, DAY_HOUR).unwrap_or_default(),
+                        visibility: hourly_value(|h| &h.visibility, DAY_HOUR),
+                        sunrise: Self::value_at(&daily.sunrise, index),
+                        sunset: Self::value_at(&daily.sunset, index),
+                    };
+
+                    forecast_weather_info.push(ForecastWeatherData {
+                        timestamp: *time,
+                        weather_data: day_weather_info,
+                    });
+                }
+            }
+        }
+
+        forecast_weather_info
+    }
+
+    fn hourly_weather_data_from_response(
+        weather_response: &Option<ForecastResponse>,
+    ) -> Vec<HourlyWeatherData> {
+        let mut hourly_weather_info: Vec<HourlyWeatherData> = vec![];
+
+        if let Some(weather_data) = weather_response {
+            if let Some(hourly) = &weather_data.hourly {
+                // the hourly data starts at the local midnight
+                let first_index = weather_data
+                    .current
+                    .as_ref()
+                    .and_then(|current| Self::hourly_index(hourly, current.time))
+                    .unwrap_or(0);
+
+                for (index, time) in hourly
+                    .time
+                    .iter()
+                    .enumerate()
+                    .skip(first_index)
+                    .take(HOURLY_FORECAST_LENGTH)
+                {
+                    let weather_code =
+                        Self::value_at(&hourly.weather_code, index).unwrap_or(u8::MAX);
+
+                    let precipitation = PrecipitationData {
+                        probability: Self::value_at(&hourly.precipitation_probability, index)
+                            .unwrap_or_default()
+                            / 100.0,
+                        rain_volume: Self::value_at(&hourly.rain, index).unwrap_or_default(),
+                        snow_volume: Self::value_at(&hourly.snowfall, index).unwrap_or_default()
+                            * SNOWFALL_CM_TO_MM,
+                    };
+
+                    hourly_weather_info.push(HourlyWeatherData {
+                        timestamp: *time,
+                        description: Self::description_from_weather_code(weather_code),
+                        condition: Self::weather_condition_from_weather_code(weather_code),
+                        temperature: Self::value_at(&hourly.temperature_2m, index)
+                            .unwrap_or_default(),
+                        precipitation,
+                    });
+                }
+            }
+        }
+
+        hourly_weather_info
+    }
+
+    // Fetches the weather for all the cities, or only for the ones with data older than max_age.
+    async fn refresh_clients(
+        &self,
+        max_age: Option<Duration>,
+    ) -> Result<Vec<CityWeatherData>, Box<dyn std::error::Error + Send + Sync>> {
+        log::debug!("Refreshing the clients, max age: {max_age:?}");
+        let now = chrono::Utc::now().timestamp();
+
+        // the weather is fetched on a copy, so the list is not locked during the requests
+        let city_clients_copy: Vec<OpenMeteoClient> = self
+            .city_clients
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|client| max_age.is_none_or(|max_age| !client.is_fresh(now, max_age)))
+            .cloned()
+            .collect();
+        let weather_api = &self.weather_api;
+
+        let mut refreshed_clients: Vec<OpenMeteoClient> = stream::iter(city_clients_copy)
+            .map(|mut client| async move {
+                if let Err(e) = client.refresh_weather(weather_api).await {
+                    log::warn!("Failed to refresh {}: {}", client.city_data.city_name, e);
+                }
+                client
+            })
+            .buffer_unordered(self.refresh_concurrency)
+            .collect()
+            .await;
+        log::debug!("Refreshing weather finished!");
+
+        // cities removed in the meantime are skipped, cities added in the meantime are kept
+        let mut city_clients = self.city_clients.lock().unwrap();
+        for client in city_clients.iter_mut() {
+            if let Some(refreshed_client) = refreshed_clients.iter_mut().find(|refreshed_client| {
+                refreshed_client.city_data.is_same_place(&client.city_data)
+            }) {
+                client.weather = refreshed_client.weather.take();
+                client.refresh_status = refreshed_client.refresh_status.clone();
+                client.fetched_at = refreshed_client.fetched_at;
+            }
+        }
+
+        Ok(city_clients
+            .iter()
+            .map(Self::city_weather_data_from_client)
+            .collect())
+    }
+
+    fn contains_city(&self, city: &CityData) -> bool {
+        self.city_clients
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|client| client.city_data.is_same_place(city))
+    }
+
+    // The response is converted right away, so the stored data does not depend on the api.
+    fn stored_weather_from_response(response: ForecastResponse) -> StoredWeather {
+        let observed_at = response.current.as_ref().map(|current| current.time);
+        let utc_offset = response.utc_offset_seconds;
+        let weather_response = Some(response);
+
+        // Open-Meteo provides neither a minute-by-minute nowcast nor weather alerts
+        StoredWeather {
+            weather_data: WeatherData {
+                current_data: Self::current_day_weather_data_from_response(&weather_response),
+                forecast_data: Self::forecast_day_weather_data_from_response(&weather_response),
+                hourly_data: Self::hourly_weather_data_from_response(&weather_response),
+                minutely_data: vec![],
+                utc_offset,
+            },
+            alerts: vec![],
+            observed_at,
+        }
+    }
+
+    // Cities stored by the previous versions contain the raw api response.
+    fn legacy_weather(weather_data: serde_json::Value) -> Option<StoredWeather> {
+        match serde_json::from_value::<ForecastResponse>(weather_data) {
+            Ok(response) => Some(Self::stored_weather_from_response(response)),
+            Err(e) => {
+                log::warn!("Stored weather response cannot be migrated: {}", e);
+                None
+            }
+        }
+    }
+
+    fn city_weather_data_from_client(city_client: &OpenMeteoClient) -> CityWeatherData {
+        let weather = city_client.weather.clone().unwrap_or_default();
+
+        CityWeatherData {
+            city_data: city_client.city_data.clone(),
+            weather_data: weather.weather_data,
+            alerts: weather.alerts,
+            refresh_status: city_client.refresh_status.clone(),
+            fetched_at: city_client.fetched_at,
+            observed_at: weather.observed_at,
+        }
+    }
+
+    fn geo_location_data_from_response(response: &GeocodingResult) -> GeoLocationData {
+        GeoLocationData {
+            name: response.name.clone(),
+            state: response.admin1.clone(),
+            timezone: response.timezone.clone(),
+            country: response.country_code.clone().unwrap_or_default(),
+            lat: response.latitude,
+            lon: response.longitude,
+        }
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl WeatherController for OpenMeteoController {
+    fn load(&self) -> Result<LoadStatus, Box<dyn std::error::Error + Send + Sync>> {
+        migrate_legacy_key(
+            self.state_store.as_ref(),
+            LEGACY_CITIES_STORED_KEY,
+            CITIES_STORED_KEY,
+        )?;
+        let loaded_cities = read_stored_cities(
+            self.state_store.as_ref(),
+            CITIES_STORED_KEY,
+            PROVIDER_NAME,
+            &Self::legacy_weather,
+        )?
+        // nothing is stored on the first start
+        .unwrap_or_default();
+        log::debug!("Successfully loaded {} cities", loaded_cities.cities.len());
+
+        *self.city_clients.lock().unwrap() = loaded_cities
+            .cities
+            .into_iter()
+            .map(OpenMeteoClient::from_stored_city)
+            .collect();
+
+        Ok(match loaded_cities.recovered {
+            true => LoadStatus::Recovered,
+            false => LoadStatus::Loaded,
+        })
+    }
+
+    fn save(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let stored_cities: Vec<StoredCity> = self
+            .city_clients
+            .lock()
+            .unwrap()
+            .iter()
+            .map(OpenMeteoClient::stored_city)
+            .collect();
+        write_stored_cities(
+            self.state_store.as_ref(),
+            CITIES_STORED_KEY,
+            PROVIDER_NAME,
+            &stored_cities,
+        )
+    }
+
+    async fn refresh_cities(
+        &self,
+    ) -> Result<Vec<CityWeatherData>, Box<dyn std::error::Error + Send + Sync>> {
+        self.refresh_clients(None).await
+    }
+
+    async fn refresh_outdated_cities(
+        &self,
+        max_age: Duration,
+    ) -> Result<Vec<CityWeatherData>, Box<dyn std::error::Error + Send + Sync>> {
+        self.refresh_clients(Some(max_age)).await
+    }
+
+    fn cached_cities(
+        &self,
+    ) -> Result<Vec<CityWeatherData>, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self
+            .city_clients
+            .lock()
+            .unwrap()
+            .iter()
+            .map(Self::city_weather_data_from_client)
+            .collect())
+    }
+
+    async fn add_city(
+        &self,
+        city: CityData,
+    ) -> Result<Option<CityWeatherData>, Box<dyn std::error::Error + Send + Sync>> {
+        log::debug!("Adding new city: {city:?}");
+
+        if self.contains_city(&city) {
+            log::info!("City already present in list!");
+            return Ok(None);
+        }
+
+        let mut client = OpenMeteoClient::new(city.clone());
+        client.refresh_weather(&self.weather_api).await?;
+
+        // the same city could have been added while the weather was fetched
+        if self.contains_city(&city) {
+            log::info!("City already present in list!");
+            return Ok(None);
+        }
+
+        let city_weather_data = Self::city_weather_data_from_client(&client);
+        self.city_clients.lock().unwrap().push(client);
+        Ok(Some(city_weather_data))
+    }
+
+    fn reorder_cities(
+        &self,
+        index: usize,
+        new_index: usize,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        move_element(&mut self.city_clients.lock().unwrap(), index, new_index)
+    }
+
+    fn remove_city(&self, index: usize) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        remove_element(
+            &mut self.city_clients.lock().unwrap(),
+            index,
+            &mut self.removed_clients.lock().unwrap(),
+        )
+    }
+
+    fn undo_remove(&self) -> Result<Vec<usize>, Box<dyn std::error::Error + Send + Sync>> {
+        // locked in the same order as on removal
+        let mut city_clients = self.city_clients.lock().unwrap();
+        Ok(self
+            .removed_clients
+            .lock()
+            .unwrap()
+            .restore(&mut city_clients, chrono::Utc::now()))
+    }
+
+    fn annotate_city(
+        &self,
+        index: usize,
+        label: Option<String>,
+        note: Option<String>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut city_clients = self.city_clients.lock().unwrap();
+        let client = city_clients
+            .get_mut(index)
+            .ok_or_else(index_out_of_bounds)?;
+        client.city_data.annotate(label, note);
+        Ok(())
+    }
+
+    fn set_cities(
+        &self,
+        cities: Vec<CityData>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut city_clients = self.city_clients.lock().unwrap();
+        let mut previous_clients = std::mem::take(&mut *city_clients);
+
+        *city_clients = cities
+            .into_iter()
+            .map(|city| {
+                match previous_clients
+                    .iter()
+                    .position(|client| client.city_data.is_same_place(&city))
+                {
+                    Some(index) => {
+                        let mut client = previous_clients.swap_remove(index);
+                        // also the label and the note of the user
+                        client.city_data = city;
+                        client
+                    }
+                    None => OpenMeteoClient::new(city),
+                }
+            })
+            .collect();
+        Ok(())
+    }
+
+    async fn search_location(
+        &self,
+        query: String,
+    ) -> Result<Vec<GeoLocationData>, Box<dyn std::error::Error + Send + Sync>> {
+        log::debug!("Searching for: {query}");
+
+        if query.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let response_data = self.weather_api.search(&query).await?;
+        log::debug!("Search result: {response_data:?}");
+
+        let mut unique_response_data: Vec<GeocodingResult> = Vec::new();
+        for element in response_data {
+            if !unique_response_data.iter().any(|existing_element| {
+                existing_element.name == element.name
+                    && existing_element.country_code == element.country_code
+                    && existing_element.admin1 == element.admin1
+            }) {
+                unique_response_data.push(element);
+            }
+        }
+
+        Ok(unique_response_data
+            .iter()
+            .map(Self::geo_location_data_from_response)
+            .collect())
+    }
+}
+
+impl OpenMeteoApi {
+    async fn forecast(
+        &self,
+        lat: f64,
+        lon: f64,
+    ) -> Result<ForecastResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let url = reqwest::Url::parse_with_params(
+            &self.forecast_url,
+            &[
+                ("latitude", lat.to_string()),
+                ("longitude", lon.to_string()),
+                ("current", CURRENT_VARIABLES.to_string()),
+                ("hourly", HOURLY_VARIABLES.to_string()),
+                ("daily", DAILY_VARIABLES.to_string()),
+                ("forecast_days", FORECAST_DAYS.to_string()),
+                ("wind_speed_unit", "ms".to_string()),
+                ("timeformat", "unixtime".to_string()),
+                ("timezone", "auto".to_string()),
+            ],
+        )?;
+
+        Ok(self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    async fn search(
+        &self,
+        query: &str,
+    ) -> Result<Vec<GeocodingResult>, Box<dyn std::error::Error + Send + Sync>> {
+        let url = reqwest::Url::parse_with_params(
+            &self.geocoding_url,
+            &[
+                ("name", query.to_string()),
+                ("count", SEARCH_RESULTS_COUNT.to_string()),
+                ("language", current_locale().code().to_string()),
+                ("format", "json".to_string()),
+            ],
+        )?;
+
+        let response: GeocodingResponse = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.results)
+    }
+}
+
+impl OpenMeteoClient {
+    pub fn new(city_data: CityData) -> Self {
+        Self {
+            city_data,
+            refresh_status: RefreshStatus::Fresh,
+            fetched_at: None,
+            weather: None,
+        }
+    }
+
+    pub fn from_stored_city(stored_city: StoredCity) -> Self {
+        Self {
+            city_data: stored_city.city_data,
+            refresh_status: RefreshStatus::Fresh,
+            fetched_at: stored_city.fetched_at,
+            weather: stored_city.weather,
+        }
+    }
+
+    pub fn stored_city(&self) -> StoredCity {
+        StoredCity {
+            city_data: self.city_data.clone(),
+            fetched_at: self.fetched_at,
+            weather: self.weather.clone(),
+        }
+    }
+
+    pub fn is_fresh(&self, now: i64, max_age: Duration) -> bool {
+        self.fetched_at
+            .is_some_and(|fetched_at| now - fetched_at < max_age.num_seconds())
+    }
+
+    pub async fn refresh_weather(
+        &mut self,
+        weather_api: &OpenMeteoApi,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let res = weather_api
+            .forecast(self.city_data.lat, self.city_data.lon)
+            .await;
+        log::debug!("Weather response: {res:?}");
+
+        match res {
+            Ok(response_data) => {
+                self.weather = Some(OpenMeteoController::stored_weather_from_response(
+                    response_data,
+                ));
+                self.refresh_status = RefreshStatus::Fresh;
+                self.fetched_at = Some(chrono::Utc::now().timestamp());
+                Ok(())
+            }
+            Err(e) => {
+                self.refresh_status = RefreshStatus::from_error(e.as_ref(), self.weather.is_some());
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::weather::locale::Locale;
+    use crate::weather::statestore::MemoryStateStore;
+    use crate::weather::stubserver::StubServer;
+    use std::sync::Arc;
+
+    const FORECAST_PATH: &str = "/v1/forecast";
+    const GEOCODING_PATH: &str = "/v1/search";
+
+    fn controller_with_stub_server() -> (OpenMeteoController, StubServer) {
+        let server = StubServer::start(vec![
+            (
+                FORECAST_PATH,
+                include_str!("fixtures/open_meteo_forecast.json"),
+            ),
+            (
+                GEOCODING_PATH,
+                include_str!("fixtures/open_meteo_geocoding.json"),
+            ),
+        ]);
+        let controller = OpenMeteoController::with_base_urls(
+            &server.url(FORECAST_PATH),
+            &server.url(GEOCODING_PATH),
+        );
+        (controller, server)
+    }
+
+    fn berlin() -> CityData {
+        CityData {
+            lat: 52.52,
+            lon: 13.41,
+            city_name: "Berlin".to_string(),
+            label: None,
+            note: None,
+        }
+    }
+
+    #[test]
+    fn weather_codes_are_mapped_to_conditions() {
+        let condition = OpenMeteoController::weather_condition_from_weather_code;
+        assert_eq!(condition(0), WeatherCondition::Sunny);
+        assert_eq!(condition(2), WeatherCondition::MostlyCloudy);
+        assert_eq!(condition(48), WeatherCondition::Foggy);
+        assert_eq!(condition(55), WeatherCondition::Rainy);
+        assert_eq!(condition(81), WeatherCondition::SunnyRainy);
+        assert_eq!(condition(86), WeatherCondition::Snowy);
+        assert_eq!(condition(99), WeatherCondition::Stormy);
+        assert_eq!(condition(42), WeatherCondition::Unknown);
+    }
+
+    #[test]
+    fn weather_codes_are_described_in_the_language() {
+        assert_eq!(Locale::English.weather_code_description(3), "overcast");
+        assert_eq!(Locale::German.weather_code_description(3), "bedeckt");
+        assert_eq!(
+            Locale::Russian.weather_code_description(96),
+            "гроза с градом"
+        );
+        assert_eq!(Locale::German.weather_code_description(42), "");
+    }
+
+    #[tokio::test]
+    async fn add_city_keeps_the_label_and_the_note() {
+        let (controller, _server) = controller_with_stub_server();
+
+        let city = CityData {
+            label: Some("Office".to_string()),
+            note: Some("Site B-12".to_string()),
+            ..berlin()
+        };
+        let city_weather = controller.add_city(city.clone()).await.unwrap().unwrap();
+        assert_eq!(city_weather.city_data, city);
+        assert_eq!(controller.cached_cities().unwrap()[0].city_data, city);
+    }
+
+    #[tokio::test]
+    async fn add_city_maps_the_forecast_response() {
+        let (controller, server) = controller_with_stub_server();
+
+        let city_weather = controller.add_city(berlin()).await.unwrap().unwrap();
+        assert!(server.requests()[0].contains("latitude=52.52&longitude=13.41"));
+
+        let current = &city_weather.weather_data.current_data;
+        assert_eq!(current.current_temperature, 2.3);
+        assert_eq!(current.condition, WeatherCondition::Cloudy);
+        assert_eq!(current.description, "overcast");
+        assert_eq!(current.wind_gust, Some(9.1));
+        assert_eq!(current.detailed_temperature.max, 6.1);
+        assert_eq!(current.sunrise, Some(1709531520));
+        assert_eq!(current.precipitation.probability, 0.1);
+
+        let forecast = &city_weather.weather_data.forecast_data;
+        assert_eq!(forecast.len(), FORECAST_DAYS);
+        assert_eq!(forecast[1].timestamp, 1709593200);
+        assert_eq!(forecast[1].weather_data.condition, WeatherCondition::Rainy);
+        assert_eq!(forecast[1].weather_data.precipitation.rain_volume, 6.8);
+        // snowfall is converted from cm to mm
+        assert!((forecast[2].weather_data.precipitation.snow_volume - 14.0).abs() < 1e-9);
+
+        // the hourly forecast starts with the current hour
+        let hourly = &city_weather.weather_data.hourly_data;
+        assert_eq!(hourly.len(), HOURLY_FORECAST_LENGTH);
+        assert_eq!(hourly[0].timestamp, 1709535600);
+
+        assert!(city_weather.fetched_at.is_some());
+        assert_eq!(city_weather.observed_at, Some(1709535600));
+        // the times are shown in the time zone of the city
+        assert_eq!(city_weather.weather_data.utc_offset, Some(3600));
+
+        assert_eq!(controller.cached_cities().unwrap().len(), 1);
+        assert!(controller.add_city(berlin()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn saved_cities_are_loaded() {
+        let state_store: StateStorePointer = Arc::new(MemoryStateStore::default());
+        let (mut controller, _) = controller_with_stub_server();
+        controller.state_store = state_store.clone();
+        controller.add_city(berlin()).await.unwrap();
+        controller.save().unwrap();
+
+        let (mut loaded_controller, server) = controller_with_stub_server();
+        loaded_controller.state_store = state_store;
+        assert_eq!(loaded_controller.load().unwrap(), LoadStatus::Loaded);
+
+        let cities = loaded_controller.cached_cities().unwrap();
+        assert_eq!(cities[0].city_data, berlin());
+        assert_eq!(cities[0].weather_data.current_data.current_temperature, 2.3);
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn refresh_cities_fetches_all_cities() {
+        let (controller, server) = controller_with_stub_server();
+        controller.add_city(berlin()).await.unwrap();
+
+        let cities = controller.refresh_cities().await.unwrap();
+        assert_eq!(cities.len(), 1);
+        assert_eq!(cities[0].city_data, berlin());
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn annotated_city_is_matched_by_coordinates() {
+        let (controller, server) = controller_with_stub_server();
+        controller.add_city(berlin()).await.unwrap();
+        controller
+            .annotate_city(
+                0,
+                Some(" Office ".to_string()),
+                Some("3rd floor".to_string()),
+            )
+            .unwrap();
+
+        let cities = controller.refresh_cities().await.unwrap();
+        assert_eq!(cities[0].city_data.display_name(), "Office");
+        assert_eq!(cities[0].city_data.note.as_deref(), Some("3rd floor"));
+        assert_eq!(cities[0].weather_data.current_data.current_temperature, 2.3);
+        assert_eq!(server.requests().len(), 2);
+
+        let renamed = CityData {
+            city_name: "Berlin, Mitte".to_string(),
+            ..berlin()
+        };
+        assert!(controller.add_city(renamed).await.unwrap().is_none());
+
+        controller
+            .annotate_city(0, Some(" ".to_string()), None)
+            .unwrap();
+        assert_eq!(controller.cached_cities().unwrap()[0].city_data, berlin());
+        assert!(controller.annotate_city(1, None, None).is_err());
+    }
+
+    #[tokio::test]
+    async fn failed_request_is_reported() {
+        let server = StubServer::start(vec![]);
+        let controller = OpenMeteoController::with_base_urls(
+            &server.url(FORECAST_PATH),
+            &server.url(GEOCODING_PATH),
+        );
+
+        assert!(controller.add_city(berlin()).await.is_err());
+        assert!(controller.cached_cities().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn search_location_removes_duplicates() {
+        let (controller, server) = controller_with_stub_server();
+
+        let results = controller
+            .search_location("Berlin".to_string())
+            .await
+            .unwrap();
+        assert!(server.requests()[0].contains("name=Berlin"));
+
+        let states: Vec<Option<String>> = results.iter().map(|r| r.state.clone()).collect();
+        assert_eq!(
+            states,
+            vec![
+                Some("Berlin".to_string()),
+                Some("New Hampshire".to_string()),
+                Some("Wisconsin".to_string()),
+                None,
+            ]
+        );
+        assert_eq!(results[0].country, "DE");
+        assert_eq!(results[3].country, "");
+    }
+
+    #[tokio::test]
+    async fn search_location_without_results() {
+        let server = StubServer::start(vec![(GEOCODING_PATH, r#"{"generationtime_ms":0.5}"#)]);
+        let controller = OpenMeteoController::with_base_urls(
+            &server.url(FORECAST_PATH),
+            &server.url(GEOCODING_PATH),
+        );
+
+        assert!(controller
+            .search_location("Nowhere".to_string())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,107 +1,100 @@
 #![cfg(all(not(target_arch = "wasm32"), feature = "open_weather"))]
 
-use chrono::DateTime;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration};
+use futures::stream::{self, StreamExt};
 use openweather_sdk::responses::{GeocodingResponse, OneCallResponse};
-use openweather_sdk::{Language, OpenWeather, Units};
-use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::io;
-use std::io::{BufReader, BufWriter, Write};
-use std::ops::Deref;
-use std::path::PathBuf;
-use std::sync::Arc;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::sync::Mutex;
 use std::vec;
-use tokio::sync::Mutex;
 
-use crate::weather::utils::*;
+use crate::weather::citystorage::{
+    read_stored_cities, write_stored_cities, StoredCity, StoredWeather, CITIES_STORED_KEY,
+};
+use crate::weather::locale::current_locale;
+use crate::weather::statestore::{default_state_store, StateStorePointer};
+use crate::weather::undo::UndoBuffer;
 use crate::weather::weathercontroller::{
-    CityData, CityWeatherData, DayWeatherData, ForecastWeatherData, GeoLocationData,
-    PrecipitationData, TemperatureData, WeatherCondition, WeatherController, WeatherData,
+    index_out_of_bounds, move_element, remove_element, CityData, CityWeatherData, DayWeatherData,
+    ForecastWeatherData, GeoLocationData, HourlyWeatherData, LoadStatus, MinutelyWeatherData,
+    PrecipitationData, RefreshStatus, TemperatureData, WeatherAlert, WeatherCondition,
+    WeatherController, WeatherData, DEFAULT_REFRESH_CONCURRENCY,
 };
 
-#[cfg(target_os = "android")]
-use std::env;
-
-const CITIES_STORED_FILE_NAME: &str = "cities_data.json";
-const ORGANIZATION_QUALIFIER: &str = "com"; // have to match android app name in cargo.toml
-const ORGANIZATION_NAME: &str = "felgo.demos"; // have to match android app name in cargo.toml
-const APPLICATION_NAME: &str = "rustyweather"; // have to match app android name in cargo.toml
-
-fn project_data_dir() -> Option<PathBuf> {
-    #[cfg(target_os = "android")]
-    {
-        match env::var("ANDROID_DATA") {
-            Ok(data_root) => {
-                if data_root.is_empty() {
-                    return None;
-                } else {
-                    let project_name = format!(
-                        "{}.{}.{}",
-                        ORGANIZATION_QUALIFIER, ORGANIZATION_NAME, APPLICATION_NAME
-                    );
-                    return Some(PathBuf::from(format!(
-                        "{}/data/{}/files",
-                        data_root, project_name
-                    )));
-                }
-            }
-            Err(_e) => {
-                log::warn!("Cannot read ANDROID_DATA, persistence not avaialble.");
-                return None;
-            }
-        }
-    }
+const OPEN_WEATHER_BASE_URL: &str = "https://api.openweathermap.org";
+const ONE_CALL_PATH: &str = "/data/3.0/onecall";
+const GEOCODING_PATH: &str = "/geo/1.0/direct";
+const REVERSE_GEOCODING_PATH: &str = "/geo/1.0/reverse";
 
-    #[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
-    {
-        if let Some(project_dir) = directories::ProjectDirs::from(
-            ORGANIZATION_QUALIFIER,
-            ORGANIZATION_NAME,
-            APPLICATION_NAME,
-        ) {
-            return Some(project_dir.data_dir().to_path_buf());
-        };
+const HOURLY_FORECAST_LENGTH: usize = 48;
+const SEARCH_RESULTS_LIMIT: usize = 5;
 
-        None
-    }
-}
+// location used for the api key check call
+const API_KEY_CHECK_LAT: f64 = 52.52;
+const API_KEY_CHECK_LON: f64 = 13.41;
+// name of the provider of the stored weather
+const PROVIDER_NAME: &str = "open_weather";
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Clone)]
 pub struct WeatherClient {
     pub city_data: CityData,
-    pub weather_data: Option<OneCallResponse>,
+    pub refresh_status: RefreshStatus,
+    pub fetched_at: Option<i64>,
+    pub weather: Option<StoredWeather>,
+}
+
+pub struct OpenWeatherApi {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: String,
+}
+
+// Error body returned by the OpenWeather api, e.g. for an invalid api key.
+#[derive(Deserialize)]
+struct ErrorResponse {
+    message: String,
 }
 
 pub struct OpenWeatherController {
-    tokio_runtime: tokio::runtime::Runtime,
-    weather_api: OpenWeather,
-    city_clients: Arc<Mutex<Vec<WeatherClient>>>,
-    storage_path: Option<PathBuf>,
+    weather_api: OpenWeatherApi,
+    city_clients: Mutex<Vec<WeatherClient>>,
+    removed_clients: Mutex<UndoBuffer<WeatherClient>>,
+    state_store: StateStorePointer,
+    refresh_concurrency: usize,
 }
 
 impl OpenWeatherController {
     pub fn new(api_key: String) -> Self {
-        let mut weather_api = OpenWeather::new(api_key, Units::Metric, Language::English);
-        weather_api.one_call.fields.minutely = false;
-        weather_api.one_call.fields.hourly = false;
-        weather_api.one_call.fields.alerts = false;
-
-        let storage_path;
-        if let Some(project_dir) = project_data_dir() {
-            storage_path = Some(project_dir.as_path().join(CITIES_STORED_FILE_NAME));
-        } else {
-            storage_path = None;
-            log::error!("Failed to initialize project dir. Persistent data will not be loaded");
-        }
+        Self::with_base_url(api_key, OPEN_WEATHER_BASE_URL)
+    }
 
+    pub fn with_base_url(api_key: String, base_url: &str) -> Self {
         Self {
-            tokio_runtime: tokio::runtime::Runtime::new().unwrap(),
-            weather_api,
-            city_clients: Arc::new(Mutex::new(vec![])),
-            storage_path,
+            weather_api: OpenWeatherApi {
+                client: reqwest::Client::new(),
+                base_url: base_url.trim_end_matches('/').to_string(),
+                api_key,
+            },
+            city_clients: Mutex::new(vec![]),
+            removed_clients: Mutex::new(UndoBuffer::new()),
+            state_store: default_state_store(),
+            refresh_concurrency: DEFAULT_REFRESH_CONCURRENCY,
         }
     }
 
+    pub fn set_refresh_concurrency(&mut self, limit: usize) {
+        self.refresh_concurrency = limit.max(1);
+    }
+
+    pub async fn check_api_key(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        log::debug!("Checking api key");
+        self.weather_api
+            .one_call(API_KEY_CHECK_LAT, API_KEY_CHECK_LON)
+            .await?;
+        Ok(())
+    }
+
     fn weather_condition_from_icon_icon_type(icon_type: &str) -> WeatherCondition {
         match icon_type {
             "01d" | "01n" => WeatherCondition::Sunny,
@@ -150,13 +143,43 @@ impl OpenWeatherController {
                     },
                 };
 
+                let precipitation = PrecipitationData {
+                    probability: today_weather_info.map_or(0 as f64, |info| info.pop),
+                    rain_volume: current
+                        .rain
+                        .as_ref()
+                        .and_then(|rain| rain.volume_over_last_hour)
+                        .unwrap_or(0 as f64),
+                    snow_volume: current
+                        .snow
+                        .as_ref()
+                        .and_then(|snow| snow.volume_over_last_hour)
+                        .unwrap_or(0 as f64),
+                };
+
                 return DayWeatherData {
                     description: weather_details.description.clone(),
                     condition: Self::weather_condition_from_icon_icon_type(&weather_details.icon),
                     current_temperature: current.temp,
                     detailed_temperature: detailed_temp,
-                    precipitation: PrecipitationData::default(),
-                    uv_index: 0.0,
+                    precipitation,
+                    uv_index: current.uvi,
+
+                    feels_like_temperature: current.feels_like,
+                    humidity: current.humidity as f64,
+                    pressure: current.pressure as f64,
+                    dew_point: current.dew_point,
+                    wind_speed: current.wind_speed,
+                    wind_gust: current.wind_gust,
+                    wind_direction: current.wind_deg as f64,
+                    cloud_cover: current.clouds as f64,
+                    visibility: current.visibility.map(|visibility| visibility as f64),
+                    sunrise: current
+                        .sunrise
+                        .or(today_weather_info.and_then(|info| info.sunrise)),
+                    sunset: current
+                        .sunset
+                        .or(today_weather_info.and_then(|info| info.sunset)),
                 };
             }
         }
@@ -172,7 +195,7 @@ impl OpenWeatherController {
         if let Some(weather_data) = weather_response {
             if let Some(daily_weather_data) = &weather_data.daily {
                 for day_weather_data in daily_weather_data.iter() {
-                    if let Some(datetime) = DateTime::from_timestamp(day_weather_data.datetime, 0) {
+                    if DateTime::from_timestamp(day_weather_data.datetime, 0).is_some() {
                         let weather_details = &day_weather_data.weather[0];
 
                         let detailed_temperature = TemperatureData {
@@ -200,11 +223,22 @@ impl OpenWeatherController {
                             detailed_temperature,
                             precipitation,
                             uv_index: day_weather_data.uvi,
+
+                            feels_like_temperature: day_weather_data.feels_like.day,
+                            humidity: day_weather_data.humidity as f64,
+                            pressure: day_weather_data.pressure as f64,
+                            dew_point: day_weather_data.dew_point,
+                            wind_speed: day_weather_data.wind_speed,
+                            wind_gust: day_weather_data.wind_gust,
+                            wind_direction: day_weather_data.wind_deg as f64,
+                            cloud_cover: day_weather_data.clouds as f64,
+                            visibility: None,
+                            sunrise: day_weather_data.sunrise,
+                            sunset: day_weather_data.sunset,
                         };
 
-                        // TODO: localization
                         forecast_weather_info.push(ForecastWeatherData {
-                            day_name: get_day_from_datetime(datetime),
+                            timestamp: day_weather_data.datetime,
                             weather_data: day_weather_info,
                         });
                     }
@@ -215,17 +249,181 @@ impl OpenWeatherController {
         forecast_weather_info
     }
 
+    fn hourly_weather_data_from_response(
+        weather_response: &Option<OneCallResponse>,
+    ) -> Vec<HourlyWeatherData> {
+        let mut hourly_weather_info: Vec<HourlyWeatherData> = vec![];
+
+        if let Some(weather_data) = weather_response {
+            if let Some(hourly_weather_data) = &weather_data.hourly {
+                for hour_weather_data in hourly_weather_data.iter().take(HOURLY_FORECAST_LENGTH) {
+                    let weather_details = &hour_weather_data.weather[0];
+
+                    let precipitation = PrecipitationData {
+                        probability: hour_weather_data.pop,
+                        rain_volume: hour_weather_data
+                            .rain
+                            .as_ref()
+                            .and_then(|rain| rain.volume_over_last_hour)
+                            .unwrap_or(0 as f64),
+                        snow_volume: hour_weather_data
+                            .snow
+                            .as_ref()
+                            .and_then(|snow| snow.volume_over_last_hour)
+                            .unwrap_or(0 as f64),
+                    };
+
+                    hourly_weather_info.push(HourlyWeatherData {
+                        timestamp: hour_weather_data.datetime,
+                        description: weather_details.description.clone(),
+                        condition: Self::weather_condition_from_icon_icon_type(
+                            &weather_details.icon,
+                        ),
+                        temperature: hour_weather_data.temp,
+                        precipitation,
+                    });
+                }
+            }
+        }
+
+        hourly_weather_info
+    }
+
+    fn minutely_weather_data_from_response(
+        weather_response: &Option<OneCallResponse>,
+    ) -> Vec<MinutelyWeatherData> {
+        match weather_response
+            .as_ref()
+            .and_then(|data| data.minutely.as_ref())
+        {
+            Some(minutely) => minutely
+                .iter()
+                .map(|minute_weather_data| MinutelyWeatherData {
+                    timestamp: minute_weather_data.datetime,
+                    precipitation: minute_weather_data.precipitation,
+                })
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    fn alerts_from_response(weather_response: &Option<OneCallResponse>) -> Vec<WeatherAlert> {
+        match weather_response
+            .as_ref()
+            .and_then(|data| data.alerts.as_ref())
+        {
+            Some(alerts) => alerts
+                .iter()
+                .map(|alert| WeatherAlert {
+                    sender: alert.sender_name.clone(),
+                    event: alert.event.clone(),
+                    start: alert.start,
+                    end: alert.end,
+                    description: alert.description.clone(),
+                    tags: alert.tags.clone(),
+                })
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    // Fetches the weather for all the cities, or only for the ones with data older than max_age.
+    async fn refresh_clients(
+        &self,
+        max_age: Option<Duration>,
+    ) -> Result<Vec<CityWeatherData>, Box<dyn std::error::Error + Send + Sync>> {
+        log::debug!("Refreshing the clients, max age: {max_age:?}");
+        let now = chrono::Utc::now().timestamp();
+
+        // the weather is fetched on a copy, so the list is not locked during the requests
+        let city_clients_copy: Vec<WeatherClient> = self
+            .city_clients
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|client| max_age.is_none_or(|max_age| !client.is_fresh(now, max_age)))
+            .cloned()
+            .collect();
+        let weather_api = &self.weather_api;
+
+        let mut refreshed_clients: Vec<WeatherClient> = stream::iter(city_clients_copy)
+            .map(|mut client| async move {
+                if let Err(e) = client.refresh_weather(weather_api).await {
+                    log::warn!("Failed to refresh {}: {}", client.city_data.city_name, e);
+                }
+                client
+            })
+            .buffer_unordered(self.refresh_concurrency)
+            .collect()
+            .await;
+        log::debug!("Refreshing weather finished!");
+
+        // cities removed in the meantime are skipped, cities added in the meantime are kept
+        let mut city_clients = self.city_clients.lock().unwrap();
+        for client in city_clients.iter_mut() {
+            if let Some(refreshed_client) = refreshed_clients.iter_mut().find(|refreshed_client| {
+                refreshed_client.city_data.is_same_place(&client.city_data)
+            }) {
+                client.weather = refreshed_client.weather.take();
+                client.refresh_status = refreshed_client.refresh_status.clone();
+                client.fetched_at = refreshed_client.fetched_at;
+            }
+        }
+
+        Ok(city_clients
+            .iter()
+            .map(Self::city_weather_data_from_client)
+            .collect())
+    }
+
+    fn contains_city(&self, city: &CityData) -> bool {
+        self.city_clients
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|client| client.city_data.is_same_place(city))
+    }
+
+    // The response is converted right away, so the stored data does not depend on the api.
+    fn stored_weather_from_response(response: OneCallResponse) -> StoredWeather {
+        let observed_at = response.current.as_ref().map(|current| current.dt);
+        let utc_offset = Some(response.timezone_offset as i32);
+        let weather_response = Some(response);
+
+        StoredWeather {
+            weather_data: WeatherData {
+                current_data: Self::current_day_weather_data_from_response(&weather_response),
+                forecast_data: Self::forecast_day_weather_data_from_response(&weather_response),
+                hourly_data: Self::hourly_weather_data_from_response(&weather_response),
+                minutely_data: Self::minutely_weather_data_from_response(&weather_response),
+                utc_offset,
+            },
+            alerts: Self::alerts_from_response(&weather_response),
+            observed_at,
+        }
+    }
+
+    // Cities stored by the previous versions contain the raw api response.
+    fn legacy_weather(weather_data: serde_json::Value) -> Option<StoredWeather> {
+        match serde_json::from_value::<OneCallResponse>(weather_data) {
+            Ok(response) => Some(Self::stored_weather_from_response(response)),
+            Err(e) => {
+                log::warn!("Stored weather response cannot be migrated: {}", e);
+                None
+            }
+        }
+    }
+
     fn city_weather_data_from_client(city_client: &WeatherClient) -> CityWeatherData {
-        let current_data = Self::current_day_weather_data_from_response(&city_client.weather_data);
-        let forecast_data =
-            Self::forecast_day_weather_data_from_response(&city_client.weather_data);
+        let weather = city_client.weather.clone().unwrap_or_default();
 
         CityWeatherData {
             city_data: city_client.city_data.clone(),
-            weather_data: WeatherData {
-                current_data,
-                forecast_data,
-            },
+            weather_data: weather.weather_data,
+            alerts: weather.alerts,
+            refresh_status: city_client.refresh_status.clone(),
+            fetched_at: city_client.fetched_at,
+            observed_at: weather.observed_at,
         }
     }
 
@@ -233,6 +431,7 @@ impl OpenWeatherController {
         GeoLocationData {
             name: response.name.clone(),
             state: response.state.clone(),
+            timezone: None,
             country: response.country.clone(),
             lat: response.lat,
             lon: response.lon,
@@ -240,214 +439,634 @@ impl OpenWeatherController {
     }
 }
 
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
 impl WeatherController for OpenWeatherController {
-    fn load(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(storage_path) = &self.storage_path {
-            log::debug!("Loading data from: {:?}", storage_path.to_str());
+    fn load(&self) -> Result<LoadStatus, Box<dyn std::error::Error + Send + Sync>> {
+        let loaded_cities = read_stored_cities(
+            self.state_store.as_ref(),
+            CITIES_STORED_KEY,
+            PROVIDER_NAME,
+            &Self::legacy_weather,
+        )?
+        // nothing is stored on the first start
+        .unwrap_or_default();
+        log::debug!("Successfully loaded {} cities", loaded_cities.cities.len());
 
-            let file = File::open(storage_path.as_path())?;
-            let reader = BufReader::new(file);
+        *self.city_clients.lock().unwrap() = loaded_cities
+            .cities
+            .into_iter()
+            .map(WeatherClient::from_stored_city)
+            .collect();
 
-            let city_clients_data: Vec<WeatherClient> = serde_json::from_reader(reader)?;
-            log::debug!("Successfully loaded {} cities", city_clients_data.len());
+        Ok(match loaded_cities.recovered {
+            true => LoadStatus::Recovered,
+            false => LoadStatus::Loaded,
+        })
+    }
 
-            let city_clients = self.city_clients.clone();
-            self.tokio_runtime.block_on(async move {
-                let mut city_clients = city_clients.lock().await;
-                *city_clients = city_clients_data;
-                Ok(())
-            })
-        } else {
-            Err(Box::new(io::Error::new(
-                io::ErrorKind::NotFound,
-                "Storage path not initialized",
-            )))
-        }
+    fn save(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let stored_cities: Vec<StoredCity> = self
+            .city_clients
+            .lock()
+            .unwrap()
+            .iter()
+            .map(WeatherClient::stored_city)
+            .collect();
+        write_stored_cities(
+            self.state_store.as_ref(),
+            CITIES_STORED_KEY,
+            PROVIDER_NAME,
+            &stored_cities,
+        )
     }
 
-    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(storage_path) = &self.storage_path {
-            log::debug!("Saving data to: {:?}", storage_path.to_str());
+    async fn refresh_cities(
+        &self,
+    ) -> Result<Vec<CityWeatherData>, Box<dyn std::error::Error + Send + Sync>> {
+        self.refresh_clients(None).await
+    }
 
-            // Ensure the parent directories exist
-            if let Some(parent_dir) = storage_path.parent() {
-                std::fs::create_dir_all(parent_dir)?;
-            }
+    async fn refresh_outdated_cities(
+        &self,
+        max_age: Duration,
+    ) -> Result<Vec<CityWeatherData>, Box<dyn std::error::Error + Send + Sync>> {
+        self.refresh_clients(Some(max_age)).await
+    }
 
-            let file = File::create(storage_path)?;
-            let mut writer = BufWriter::new(file);
-            let city_clients = self.city_clients.clone();
+    fn cached_cities(
+        &self,
+    ) -> Result<Vec<CityWeatherData>, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self
+            .city_clients
+            .lock()
+            .unwrap()
+            .iter()
+            .map(Self::city_weather_data_from_client)
+            .collect())
+    }
 
-            self.tokio_runtime.block_on(async move {
-                let city_clients = city_clients.lock().await;
-                serde_json::to_writer(&mut writer, city_clients.deref())?;
-                writer.flush()?;
+    async fn add_city(
+        &self,
+        city: CityData,
+    ) -> Result<Option<CityWeatherData>, Box<dyn std::error::Error + Send + Sync>> {
+        log::debug!("Adding new city: {city:?}");
 
-                Ok(())
-            })
-        } else {
-            Err(Box::new(io::Error::new(
-                io::ErrorKind::NotFound,
-                "Storage path not initialized",
-            )))
+        if self.contains_city(&city) {
+            log::info!("City already present in list!");
+            return Ok(None);
         }
-    }
-
-    fn refresh_cities(&mut self) -> Result<Vec<CityWeatherData>, Box<dyn std::error::Error>> {
-        log::debug!("Refreshing all the clients!");
 
-        let city_clients_clone = self.city_clients.clone();
-        let weather_api = self.weather_api.clone();
+        let mut client = WeatherClient::new(city.clone());
+        client.refresh_weather(&self.weather_api).await?;
 
-        self.tokio_runtime.block_on(async move {
-            let mut city_clients = city_clients_clone.lock().await;
+        // the same city could have been added while the weather was fetched
+        if self.contains_city(&city) {
+            log::info!("City already present in list!");
+            return Ok(None);
+        }
 
-            let mut errors = vec![];
-            for client in city_clients.iter_mut() {
-                // TODO: Spawn all tasks at once and join them later.
-                if let Err(e) = client.refresh_weather(&weather_api).await {
-                    errors.push(e);
-                }
-            }
-            log::debug!("Refreshing weather finished!");
+        let city_weather_data = Self::city_weather_data_from_client(&client);
+        self.city_clients.lock().unwrap().push(client);
+        Ok(Some(city_weather_data))
+    }
 
-            if !errors.is_empty() && errors.len() == city_clients.len() {
-                return Err(errors.pop().unwrap());
-            }
-            Ok(city_clients
-                .iter()
-                .map(Self::city_weather_data_from_client)
-                .collect())
-        })
+    fn reorder_cities(
+        &self,
+        index: usize,
+        new_index: usize,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        move_element(&mut self.city_clients.lock().unwrap(), index, new_index)
     }
 
-    fn add_city(
-        &mut self,
-        city: CityData,
-    ) -> Result<Option<CityWeatherData>, Box<dyn std::error::Error>> {
-        log::debug!("Adding new city: {city:?}");
-        let city_clients_clone = self.city_clients.clone();
-        let weather_api = self.weather_api.clone();
+    fn remove_city(&self, index: usize) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        remove_element(
+            &mut self.city_clients.lock().unwrap(),
+            index,
+            &mut self.removed_clients.lock().unwrap(),
+        )
+    }
 
-        self.tokio_runtime.block_on(async move {
-            let mut city_clients = city_clients_clone.lock().await;
-            match city_clients
-                .iter()
-                .position(|client| client.city_data == city)
-            {
-                Some(_) => {
-                    log::info!("City already present in list!");
-                    Ok(None)
-                }
-                None => {
-                    // Add to list and refresh
-                    let mut client = WeatherClient::new(city.lat, city.lon, &city.city_name);
-                    client.refresh_weather(&weather_api).await?;
-                    let city_weather_data = Self::city_weather_data_from_client(&client);
-                    city_clients.push(client);
-                    Ok(Some(city_weather_data))
-                }
-            }
-        })
+    fn undo_remove(&self) -> Result<Vec<usize>, Box<dyn std::error::Error + Send + Sync>> {
+        // locked in the same order as on removal
+        let mut city_clients = self.city_clients.lock().unwrap();
+        Ok(self
+            .removed_clients
+            .lock()
+            .unwrap()
+            .restore(&mut city_clients, chrono::Utc::now()))
     }
 
-    fn reorder_cities(
-        &mut self,
+    fn annotate_city(
+        &self,
         index: usize,
-        new_index: usize,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let city_clients = self.city_clients.clone();
-        self.tokio_runtime.block_on(async move {
-            let mut city_clients = city_clients.lock().await;
-            city_clients.swap(index, new_index);
-        });
+        label: Option<String>,
+        note: Option<String>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut city_clients = self.city_clients.lock().unwrap();
+        let client = city_clients
+            .get_mut(index)
+            .ok_or_else(index_out_of_bounds)?;
+        client.city_data.annotate(label, note);
         Ok(())
     }
 
-    fn remove_city(&mut self, index: usize) -> Result<(), Box<dyn std::error::Error>> {
-        let city_clients = self.city_clients.clone();
-        self.tokio_runtime.block_on(async move {
-            let mut city_clients = city_clients.lock().await;
-            city_clients.remove(index);
-        });
+    fn set_cities(
+        &self,
+        cities: Vec<CityData>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut city_clients = self.city_clients.lock().unwrap();
+        let mut previous_clients = std::mem::take(&mut *city_clients);
+
+        *city_clients = cities
+            .into_iter()
+            .map(|city| {
+                match previous_clients
+                    .iter()
+                    .position(|client| client.city_data.is_same_place(&city))
+                {
+                    Some(index) => {
+                        let mut client = previous_clients.swap_remove(index);
+                        // also the label and the note of the user
+                        client.city_data = city;
+                        client
+                    }
+                    None => WeatherClient::new(city),
+                }
+            })
+            .collect();
         Ok(())
     }
 
-    fn search_location(
+    async fn search_location(
         &self,
         query: String,
-    ) -> Result<Vec<GeoLocationData>, Box<dyn std::error::Error>> {
+    ) -> Result<Vec<GeoLocationData>, Box<dyn std::error::Error + Send + Sync>> {
         log::debug!("Searching for: {query}");
-        let weather_api = self.weather_api.clone();
 
         if query.is_empty() {
             return Ok(vec![]);
         }
 
-        self.tokio_runtime.block_on(async move {
-            let response_data = weather_api
-                .geocoding
-                .get_geocoding(&query, None, None, 0)
-                .await?;
-
-            log::debug!("Search result: {response_data:?}");
-
-            let mut unique_response_data: Vec<GeocodingResponse> = Vec::new();
-            for element in response_data {
-                if !unique_response_data.iter().any(|existing_element| {
-                    if existing_element.name == element.name
-                        && existing_element.country == element.country
-                        && existing_element.state == element.state
-                    {
-                        return true;
-                    }
-                    false
-                }) {
-                    unique_response_data.push(element);
-                }
+        let response_data = self.weather_api.geocoding(&query).await?;
+        log::debug!("Search result: {response_data:?}");
+
+        let mut unique_response_data: Vec<GeocodingResponse> = Vec::new();
+        for element in response_data {
+            if !unique_response_data.iter().any(|existing_element| {
+                existing_element.name == element.name
+                    && existing_element.country == element.country
+                    && existing_element.state == element.state
+            }) {
+                unique_response_data.push(element);
             }
+        }
 
-            Ok(unique_response_data
-                .iter()
-                .map(Self::geo_location_data_from_response)
-                .collect())
-        })
+        Ok(unique_response_data
+            .iter()
+            .map(Self::geo_location_data_from_response)
+            .collect())
+    }
+
+    async fn reverse_geocode(
+        &self,
+        lat: f64,
+        lon: f64,
+    ) -> Result<Option<GeoLocationData>, Box<dyn std::error::Error + Send + Sync>> {
+        log::debug!("Reverse geocoding: {lat}, {lon}");
+
+        let response_data = self.weather_api.reverse_geocoding(lat, lon).await?;
+        log::debug!("Reverse geocoding result: {response_data:?}");
+
+        Ok(response_data
+            .first()
+            .map(Self::geo_location_data_from_response))
+    }
+}
+
+impl OpenWeatherApi {
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        params: &[(&str, String)],
+    ) -> Result<T, Box<dyn std::error::Error + Send + Sync>> {
+        let mut url = reqwest::Url::parse(&format!("{}{}", self.base_url, path))?;
+        url.query_pairs_mut()
+            .extend_pairs(params)
+            .append_pair("appid", &self.api_key);
+
+        // the url contains the api key, so it is not part of the reported errors
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| e.without_url())?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            let message = match serde_json::from_str::<ErrorResponse>(&body) {
+                Ok(error_response) => error_response.message,
+                Err(_) => status.to_string(),
+            };
+            return Err(message.into());
+        }
+
+        Ok(response.json().await.map_err(|e| e.without_url())?)
+    }
+
+    async fn one_call(
+        &self,
+        lat: f64,
+        lon: f64,
+    ) -> Result<OneCallResponse, Box<dyn std::error::Error + Send + Sync>> {
+        self.get(
+            ONE_CALL_PATH,
+            &[
+                ("lat", lat.to_string()),
+                ("lon", lon.to_string()),
+                ("units", "metric".to_string()),
+                ("lang", current_locale().code().to_string()),
+            ],
+        )
+        .await
+    }
+
+    async fn geocoding(
+        &self,
+        query: &str,
+    ) -> Result<Vec<GeocodingResponse>, Box<dyn std::error::Error + Send + Sync>> {
+        self.get(
+            GEOCODING_PATH,
+            &[
+                ("q", query.to_string()),
+                ("limit", SEARCH_RESULTS_LIMIT.to_string()),
+            ],
+        )
+        .await
+    }
+
+    async fn reverse_geocoding(
+        &self,
+        lat: f64,
+        lon: f64,
+    ) -> Result<Vec<GeocodingResponse>, Box<dyn std::error::Error + Send + Sync>> {
+        self.get(
+            REVERSE_GEOCODING_PATH,
+            &[
+                ("lat", lat.to_string()),
+                ("lon", lon.to_string()),
+                ("limit", "1".to_string()),
+            ],
+        )
+        .await
     }
 }
 
 impl WeatherClient {
-    pub fn new(lat: f64, lon: f64, cname: &str) -> Self {
+    pub fn new(city_data: CityData) -> Self {
         Self {
-            city_data: CityData {
-                lat,
-                lon,
-                city_name: cname.to_string(),
-            },
-            weather_data: None,
+            city_data,
+            refresh_status: RefreshStatus::Fresh,
+            fetched_at: None,
+            weather: None,
         }
     }
 
+    pub fn from_stored_city(stored_city: StoredCity) -> Self {
+        Self {
+            city_data: stored_city.city_data,
+            refresh_status: RefreshStatus::Fresh,
+            fetched_at: stored_city.fetched_at,
+            weather: stored_city.weather,
+        }
+    }
+
+    pub fn stored_city(&self) -> StoredCity {
+        StoredCity {
+            city_data: self.city_data.clone(),
+            fetched_at: self.fetched_at,
+            weather: self.weather.clone(),
+        }
+    }
+
+    pub fn is_fresh(&self, now: i64, max_age: Duration) -> bool {
+        self.fetched_at
+            .is_some_and(|fetched_at| now - fetched_at < max_age.num_seconds())
+    }
+
     pub async fn refresh_weather(
         &mut self,
-        weather_api: &OpenWeather,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+        weather_api: &OpenWeatherApi,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let res = weather_api
-            .one_call
-            .call(self.city_data.lat, self.city_data.lon)
+            .one_call(self.city_data.lat, self.city_data.lon)
             .await;
         log::debug!("Weather response: {res:?}");
 
         match res {
             Ok(response_data) => {
-                self.weather_data = Some(response_data);
-                log::debug!(
-                    "Response received at: {:?}",
-                    chrono::offset::Local::now().timestamp()
-                );
+                self.weather = Some(OpenWeatherController::stored_weather_from_response(
+                    response_data,
+                ));
+                self.refresh_status = RefreshStatus::Fresh;
+                self.fetched_at = Some(chrono::Utc::now().timestamp());
+                log::debug!("Response received at: {:?}", self.fetched_at);
 
                 Ok(())
             }
-            Err(e) => Err(e),
+            Err(e) => {
+                self.refresh_status = RefreshStatus::from_error(e.as_ref(), self.weather.is_some());
+                Err(e)
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::weather::citystorage::parse_cities;
+    use crate::weather::statestore::MemoryStateStore;
+    use crate::weather::stubserver::StubServer;
+    use std::sync::Arc;
+
+    const API_KEY: &str = "test-key";
+
+    fn controller_with_stub_server() -> (OpenWeatherController, StubServer) {
+        let server = StubServer::start(vec![
+            (
+                ONE_CALL_PATH,
+                include_str!("fixtures/open_weather_one_call.json"),
+            ),
+            (
+                GEOCODING_PATH,
+                include_str!("fixtures/open_weather_geocoding.json"),
+            ),
+            (
+                REVERSE_GEOCODING_PATH,
+                include_str!("fixtures/open_weather_reverse_geocoding.json"),
+            ),
+        ]);
+        let controller = OpenWeatherController::with_base_url(API_KEY.to_string(), &server.url(""));
+        (controller, server)
+    }
+
+    fn berlin() -> CityData {
+        CityData {
+            lat: 52.52,
+            lon: 13.41,
+            city_name: "Berlin".to_string(),
+            label: None,
+            note: None,
+        }
+    }
+
+    fn munich() -> CityData {
+        CityData {
+            lat: 48.14,
+            lon: 11.58,
+            city_name: "Munich".to_string(),
+            label: None,
+            note: None,
+        }
+    }
+
+    #[test]
+    fn icons_are_mapped_to_conditions() {
+        let condition = OpenWeatherController::weather_condition_from_icon_icon_type;
+        assert_eq!(condition("01d"), WeatherCondition::Sunny);
+        assert_eq!(condition("02n"), WeatherCondition::PartiallyCloudy);
+        assert_eq!(condition("03d"), WeatherCondition::MostlyCloudy);
+        assert_eq!(condition("04n"), WeatherCondition::Cloudy);
+        assert_eq!(condition("09d"), WeatherCondition::Rainy);
+        assert_eq!(condition("10n"), WeatherCondition::SunnyRainy);
+        assert_eq!(condition("11d"), WeatherCondition::Stormy);
+        assert_eq!(condition("13n"), WeatherCondition::Snowy);
+        assert_eq!(condition("50d"), WeatherCondition::Foggy);
+        assert_eq!(condition("99x"), WeatherCondition::Unknown);
+    }
+
+    #[tokio::test]
+    async fn add_city_keeps_the_label_and_the_note() {
+        let (controller, _server) = controller_with_stub_server();
+
+        let city = CityData {
+            label: Some("Office".to_string()),
+            note: Some("Site B-12".to_string()),
+            ..berlin()
+        };
+        let city_weather = controller.add_city(city.clone()).await.unwrap().unwrap();
+        assert_eq!(city_weather.city_data, city);
+        assert_eq!(controller.cached_cities().unwrap()[0].city_data, city);
+    }
+
+    #[tokio::test]
+    async fn add_city_maps_the_one_call_response() {
+        let (controller, server) = controller_with_stub_server();
+
+        let city_weather = controller.add_city(berlin()).await.unwrap().unwrap();
+        let request = &server.requests()[0];
+        assert!(request.starts_with(ONE_CALL_PATH));
+        assert!(request.contains("lat=52.52&lon=13.41"));
+        assert!(request.contains("units=metric"));
+        assert!(request.contains("lang=en"));
+        assert!(request.contains("appid=test-key"));
+
+        let current = &city_weather.weather_data.current_data;
+        assert_eq!(current.current_temperature, 2.3);
+        assert_eq!(current.condition, WeatherCondition::SunnyRainy);
+        assert_eq!(current.description, "light rain");
+        assert_eq!(current.precipitation.rain_volume, 0.21);
+        assert_eq!(current.precipitation.probability, 0.1);
+        assert_eq!(current.detailed_temperature.max, 6.1);
+        assert_eq!(current.wind_gust, Some(9.1));
+        assert_eq!(current.visibility, Some(10000.0));
+        assert_eq!(current.sunrise, Some(1709531520));
+
+        let forecast = &city_weather.weather_data.forecast_data;
+        let conditions: Vec<WeatherCondition> = forecast
+            .iter()
+            .map(|day| day.weather_data.condition.clone())
+            .collect();
+        assert_eq!(
+            conditions,
+            vec![
+                WeatherCondition::Cloudy,
+                WeatherCondition::SunnyRainy,
+                WeatherCondition::Snowy,
+                WeatherCondition::MostlyCloudy,
+                WeatherCondition::Rainy,
+                WeatherCondition::Sunny,
+                WeatherCondition::Foggy,
+                WeatherCondition::Stormy,
+            ]
+        );
+        assert_eq!(forecast[1].timestamp, 1709636400);
+        assert_eq!(forecast[1].weather_data.precipitation.rain_volume, 6.8);
+        assert_eq!(forecast[2].weather_data.precipitation.snow_volume, 1.4);
+        assert_eq!(forecast[2].weather_data.detailed_temperature.min, -1.8);
+
+        let hourly = &city_weather.weather_data.hourly_data;
+        assert_eq!(hourly.len(), HOURLY_FORECAST_LENGTH);
+        assert_eq!(hourly[0].timestamp, 1709535600);
+
+        assert_eq!(city_weather.weather_data.minutely_data.len(), 61);
+        assert_eq!(city_weather.alerts.len(), 1);
+        assert_eq!(city_weather.alerts[0].event, "Wind Gusts Warning");
+
+        assert!(city_weather.fetched_at.is_some());
+        assert_eq!(city_weather.observed_at, Some(1709535600));
+        assert_eq!(city_weather.weather_data.utc_offset, Some(3600));
+
+        assert_eq!(controller.cached_cities().unwrap().len(), 1);
+        assert!(controller.add_city(berlin()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn saved_cities_are_loaded() {
+        let state_store: StateStorePointer = Arc::new(MemoryStateStore::default());
+        let (mut controller, _) = controller_with_stub_server();
+        controller.state_store = state_store.clone();
+        controller.add_city(berlin()).await.unwrap();
+        controller.save().unwrap();
+
+        let (mut loaded_controller, server) = controller_with_stub_server();
+        loaded_controller.state_store = state_store;
+        assert_eq!(loaded_controller.load().unwrap(), LoadStatus::Loaded);
+
+        let cities = loaded_controller.cached_cities().unwrap();
+        assert_eq!(cities[0].city_data, berlin());
+        assert_eq!(cities[0].weather_data.current_data.current_temperature, 2.3);
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn refresh_cities_fetches_all_cities() {
+        let (controller, server) = controller_with_stub_server();
+        controller.add_city(berlin()).await.unwrap();
+
+        let cities = controller.refresh_cities().await.unwrap();
+        assert_eq!(cities.len(), 1);
+        assert_eq!(cities[0].city_data, berlin());
+        assert_eq!(cities[0].refresh_status, RefreshStatus::Fresh);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn refresh_outdated_cities_skips_fresh_cities() {
+        let (controller, server) = controller_with_stub_server();
+        controller.add_city(berlin()).await.unwrap();
+
+        let cities = controller
+            .refresh_outdated_cities(Duration::minutes(30))
+            .await
+            .unwrap();
+        assert_eq!(cities.len(), 1);
+        assert_eq!(server.requests().len(), 1);
+
+        controller
+            .refresh_outdated_cities(Duration::zero())
+            .await
+            .unwrap();
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn refresh_cities_reports_failed_cities() {
+        let server = StubServer::start(vec![]);
+        let controller = OpenWeatherController::with_base_url(API_KEY.to_string(), &server.url(""));
+
+        let response: OneCallResponse =
+            serde_json::from_str(include_str!("fixtures/open_weather_one_call.json")).unwrap();
+        let mut cached_client = WeatherClient::new(berlin());
+        cached_client.weather = Some(OpenWeatherController::stored_weather_from_response(
+            response,
+        ));
+        *controller.city_clients.lock().unwrap() =
+            vec![cached_client, WeatherClient::new(munich())];
+
+        let cities = controller.refresh_cities().await.unwrap();
+        assert!(matches!(cities[0].refresh_status, RefreshStatus::Stale(_)));
+        assert_eq!(cities[0].weather_data.current_data.current_temperature, 2.3);
+        assert!(matches!(cities[1].refresh_status, RefreshStatus::Failed(_)));
+        assert_eq!(server.requests().len(), 2);
+
+        // the status is kept for displaying the cached data
+        let cached = controller.cached_cities().unwrap();
+        assert!(matches!(cached[1].refresh_status, RefreshStatus::Failed(_)));
+    }
+
+    #[test]
+    fn stored_response_is_migrated() {
+        let stored = format!(
+            r#"[{{"city_data":{{"lat":52.52,"lon":13.41,"city_name":"Berlin"}},"weather_data":{}}}]"#,
+            include_str!("fixtures/open_weather_one_call.json")
+        );
+
+        let cities = parse_cities(&stored, &OpenWeatherController::legacy_weather).unwrap();
+        let weather = cities[0].weather.as_ref().unwrap();
+        assert_eq!(weather.weather_data.current_data.current_temperature, 2.3);
+        assert_eq!(weather.observed_at, Some(1709535600));
+    }
+
+    #[tokio::test]
+    async fn invalid_api_key_is_reported() {
+        let server = StubServer::start_with_status(
+            "401 Unauthorized",
+            vec![(
+                ONE_CALL_PATH,
+                r#"{"cod":401,"message":"Invalid API key. Please see https://openweathermap.org/faq#error401 for more info."}"#,
+            )],
+        );
+        let controller = OpenWeatherController::with_base_url(API_KEY.to_string(), &server.url(""));
+
+        let error = controller.check_api_key().await.unwrap_err();
+        assert!(error.to_string().starts_with("Invalid API key."));
+    }
+
+    #[tokio::test]
+    async fn search_location_removes_duplicates() {
+        let (controller, server) = controller_with_stub_server();
+
+        let results = controller
+            .search_location("Berlin".to_string())
+            .await
+            .unwrap();
+        let request = &server.requests()[0];
+        assert!(request.starts_with(GEOCODING_PATH));
+        assert!(request.contains("q=Berlin"));
+
+        let states: Vec<Option<String>> = results.iter().map(|r| r.state.clone()).collect();
+        assert_eq!(
+            states,
+            vec![
+                Some("Berlin".to_string()),
+                Some("New Hampshire".to_string()),
+                Some("Wisconsin".to_string()),
+                None,
+            ]
+        );
+        assert_eq!(results[0].lat, 52.5170365);
+        assert_eq!(results[3].country, "ZA");
+    }
+
+    #[tokio::test]
+    async fn reverse_geocode_names_the_place() {
+        let (controller, server) = controller_with_stub_server();
+
+        let location = controller
+            .reverse_geocode(52.5186, 13.4081)
+            .await
+            .unwrap()
+            .unwrap();
+        let request = &server.requests()[0];
+        assert!(request.starts_with(REVERSE_GEOCODING_PATH));
+        assert!(request.contains("lat=52.5186"));
+        assert!(request.contains("lon=13.4081"));
+
+        assert_eq!(location.name, "Mitte");
+        assert_eq!(location.state.as_deref(), Some("Berlin"));
+    }
+}
@@ -0,0 +1,179 @@
+use chrono::Duration;
+
+use crate::weather::weathercontroller::{CityWeatherData, RefreshStatus};
+
+pub const DEFAULT_CACHE_TTL_MINUTES: i64 = 30;
+
+// The first retry after a failed refresh, doubled after each failure up to the cache ttl.
+const MIN_RETRY_DELAY_MINUTES: i64 = 1;
+
+// Decides when the cached weather is outdated and has to be fetched again.
+// The scheduler does not run anything on its own, it is asked on each timer tick,
+// so the same policy applies to all the platforms.
+pub struct RefreshScheduler {
+    cache_ttl: Duration,
+    retry_delay: Option<Duration>,
+    next_refresh: i64,
+    refreshing: bool,
+    // forced refreshes waiting for the running one instead of fetching the same cities again
+    joined: usize,
+}
+
+impl RefreshScheduler {
+    pub fn new(cache_ttl: Duration) -> Self {
+        Self {
+            cache_ttl,
+            retry_delay: None,
+            next_refresh: 0,
+            refreshing: false,
+            joined: 0,
+        }
+    }
+
+    pub fn cache_ttl(&self) -> Duration {
+        self.cache_ttl
+    }
+
+    // Returns true and marks the refresh as started if it is due.
+    pub fn try_start(&mut self) -> bool {
+        if self.refreshing || chrono::Utc::now().timestamp() < self.next_refresh {
+            return false;
+        }
+        self.start();
+        true
+    }
+
+    // Marks a refresh as started regardless of the schedule, e.g. a forced one.
+    pub fn start(&mut self) {
+        self.refreshing = true;
+    }
+
+    // Marks a forced refresh as started, or joins the running refresh and returns false.
+    pub fn try_start_forced(&mut self) -> bool {
+        if self.refreshing {
+            self.joined += 1;
+            return false;
+        }
+        self.start();
+        true
+    }
+
+    // Schedules the next refresh, None means the whole refresh failed.
+    // Returns the number of the forced refreshes that joined the finished one.
+    pub fn finish(&mut self, cities_data: Option<&[CityWeatherData]>) -> usize {
+        self.refreshing = false;
+        let joined = std::mem::take(&mut self.joined);
+        let now = chrono::Utc::now().timestamp();
+
+        let failed = match cities_data {
+            Some(cities_data) => cities_data
+                .iter()
+                .any(|data| data.refresh_status != RefreshStatus::Fresh),
+            None => true,
+        };
+
+        if failed {
+            // most likely offline, retry later but not more often than needed
+            let retry_delay = match self.retry_delay {
+                Some(delay) => (delay * 2).min(self.cache_ttl),
+                None => Duration::minutes(MIN_RETRY_DELAY_MINUTES).min(self.cache_ttl),
+            };
+            log::debug!("Refresh failed, retrying in {retry_delay:?}");
+            self.retry_delay = Some(retry_delay);
+            self.next_refresh = now + retry_delay.num_seconds();
+            return joined;
+        }
+
+        self.retry_delay = None;
+
+        // the next refresh is due when the oldest cached data expires
+        let next_expiry = cities_data
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|data| data.fetched_at)
+            .min()
+            .map_or(now + self.cache_ttl.num_seconds(), |fetched_at| {
+                fetched_at + self.cache_ttl.num_seconds()
+            });
+        self.next_refresh =
+            next_expiry.max(now + Duration::minutes(MIN_RETRY_DELAY_MINUTES).num_seconds());
+        joined
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::weather::weathercontroller::{CityData, DayWeatherData, WeatherData};
+
+    fn city_with_status(refresh_status: RefreshStatus, fetched_at: i64) -> CityWeatherData {
+        CityWeatherData {
+            city_data: CityData {
+                lat: 52.52,
+                lon: 13.41,
+                city_name: "Berlin".to_string(),
+                label: None,
+                note: None,
+            },
+            weather_data: WeatherData {
+                current_data: DayWeatherData::default(),
+                forecast_data: vec![],
+                hourly_data: vec![],
+                minutely_data: vec![],
+                utc_offset: None,
+            },
+            alerts: vec![],
+            refresh_status,
+            fetched_at: Some(fetched_at),
+            observed_at: None,
+        }
+    }
+
+    #[test]
+    fn refresh_is_not_started_twice() {
+        let mut scheduler = RefreshScheduler::new(Duration::minutes(30));
+        assert!(scheduler.try_start());
+        assert!(!scheduler.try_start());
+    }
+
+    #[test]
+    fn forced_refresh_joins_the_running_one() {
+        let mut scheduler = RefreshScheduler::new(Duration::minutes(30));
+        assert!(scheduler.try_start());
+        assert!(!scheduler.try_start_forced());
+        assert_eq!(scheduler.finish(Some(&[])), 1);
+
+        assert!(scheduler.try_start_forced());
+        assert_eq!(scheduler.finish(Some(&[])), 0);
+    }
+
+    #[test]
+    fn next_refresh_waits_for_the_oldest_data_to_expire() {
+        let mut scheduler = RefreshScheduler::new(Duration::minutes(30));
+        let now = chrono::Utc::now().timestamp();
+
+        scheduler.start();
+        scheduler.finish(Some(&[
+            city_with_status(RefreshStatus::Fresh, now),
+            city_with_status(RefreshStatus::Fresh, now - 600),
+        ]));
+        assert_eq!(scheduler.next_refresh, now + 1200);
+        assert!(!scheduler.try_start());
+    }
+
+    #[test]
+    fn failed_refresh_backs_off() {
+        let mut scheduler = RefreshScheduler::new(Duration::minutes(3));
+        let failed = [city_with_status(RefreshStatus::Failed("offline".into()), 0)];
+
+        scheduler.finish(Some(&failed));
+        assert_eq!(scheduler.retry_delay, Some(Duration::minutes(1)));
+        scheduler.finish(None);
+        assert_eq!(scheduler.retry_delay, Some(Duration::minutes(2)));
+        scheduler.finish(None);
+        assert_eq!(scheduler.retry_delay, Some(Duration::minutes(3)));
+
+        scheduler.finish(Some(&[]));
+        assert_eq!(scheduler.retry_delay, None);
+    }
+}
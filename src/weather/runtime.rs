@@ -0,0 +1,36 @@
+use std::future::Future;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::OnceLock;
+
+// The http clients need the tokio reactor, so all the weather tasks share one runtime.
+#[cfg(not(target_arch = "wasm32"))]
+fn runtime() -> &'static tokio::runtime::Runtime {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+
+    RUNTIME.get_or_init(|| tokio::runtime::Runtime::new().expect("Cannot create tokio runtime!"))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn spawn_task<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    runtime().spawn(future);
+}
+
+// Waits for the future, used by the command line interface that has no event loop.
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg_attr(target_os = "android", allow(dead_code))]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    runtime().block_on(future)
+}
+
+// The futures run on the browser event loop.
+#[cfg(target_arch = "wasm32")]
+pub fn spawn_task<F>(future: F)
+where
+    F: Future<Output = ()> + 'static,
+{
+    wasm_bindgen_futures::spawn_local(future);
+}
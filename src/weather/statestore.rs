@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::weather::utils::project_data_dir;
+
+pub type StateStorePointer = Arc<dyn StateStore + Send + Sync>;
+
+// Persistent key-value storage for the application state.
+pub trait StateStore {
+    // None means nothing is stored under the key.
+    fn read(&self, key: &str) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>>;
+
+    // The previous data is replaced at once, a failed write keeps it untouched.
+    fn write(&self, key: &str, data: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+}
+
+// Files in the application data directory on the desktop and Android, Web localStorage in the browser.
+pub fn default_state_store() -> StateStorePointer {
+    #[cfg(target_arch = "wasm32")]
+    {
+        Arc::new(LocalStorageStateStore)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        match project_data_dir() {
+            Some(project_dir) => Arc::new(FileStateStore::new(project_dir)),
+            None => {
+                log::error!("Failed to initialize project dir. Persistent data will not be loaded");
+                Arc::new(MemoryStateStore::default())
+            }
+        }
+    }
+}
+
+// Keeps the data only while the application is running.
+#[derive(Default)]
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+pub struct MemoryStateStore {
+    data: Mutex<HashMap<String, String>>,
+}
+
+impl StateStore for MemoryStateStore {
+    fn read(&self, key: &str) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.data.lock().unwrap().get(key).cloned())
+    }
+
+    fn write(&self, key: &str, data: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.data
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), data.to_string());
+        Ok(())
+    }
+}
+
+// Stores each key as a file in the given directory.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct FileStateStore {
+    dir: PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileStateStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl StateStore for FileStateStore {
+    fn read(&self, key: &str) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let path = self.dir.join(key);
+        log::debug!("Reading data from: {:?}", path.to_str());
+
+        match std::fs::read_to_string(&path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // The data is written to a temporary file first and moved in place when complete,
+    // so an interrupted save never leaves a truncated file behind.
+    fn write(&self, key: &str, data: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let path = self.dir.join(key);
+        log::debug!("Writing data to: {:?}", path.to_str());
+
+        // Ensure the parent directories exist
+        std::fs::create_dir_all(&self.dir)?;
+
+        let temp_path = self.dir.join(format!("{}.tmp", key));
+        let mut file = std::fs::File::create(&temp_path)?;
+        std::io::Write::write_all(&mut file, data.as_bytes())?;
+        file.sync_all()?;
+
+        std::fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+}
+
+// Web localStorage of the page, the keys are prefixed as the origin can be shared with other pages.
+#[cfg(target_arch = "wasm32")]
+pub struct LocalStorageStateStore;
+
+#[cfg(target_arch = "wasm32")]
+impl LocalStorageStateStore {
+    const KEY_PREFIX: &'static str = "rusty_weather.";
+
+    fn storage() -> Result<web_sys::Storage, Box<dyn std::error::Error + Send + Sync>> {
+        web_sys::window()
+            .and_then(|window| window.local_storage().ok().flatten())
+            .ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::NotFound, "Local storage not available")
+                    .into()
+            })
+    }
+
+    fn js_error(error: wasm_bindgen::JsValue) -> Box<dyn std::error::Error + Send + Sync> {
+        Box::new(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!("{:?}", error),
+        ))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl StateStore for LocalStorageStateStore {
+    fn read(&self, key: &str) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        Self::storage()?
+            .get_item(&format!("{}{}", Self::KEY_PREFIX, key))
+            .map_err(Self::js_error)
+    }
+
+    fn write(&self, key: &str, data: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        Self::storage()?
+            .set_item(&format!("{}{}", Self::KEY_PREFIX, key), data)
+            .map_err(Self::js_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_store_keeps_the_data() {
+        let store = MemoryStateStore::default();
+        assert_eq!(store.read("cities").unwrap(), None);
+
+        store.write("cities", "[]").unwrap();
+        assert_eq!(store.read("cities").unwrap().as_deref(), Some("[]"));
+    }
+
+    #[test]
+    fn file_store_replaces_the_file() {
+        let dir = std::env::temp_dir().join(format!(
+            "rusty-weather-test-file-store-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = FileStateStore::new(dir.clone());
+        assert_eq!(store.read("cities.json").unwrap(), None);
+
+        store.write("cities.json", "first").unwrap();
+        store.write("cities.json", "second").unwrap();
+        assert_eq!(
+            store.read("cities.json").unwrap().as_deref(),
+            Some("second")
+        );
+        assert!(!dir.join("cities.json.tmp").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,86 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// Local HTTP server replaying recorded responses, matched by the request path.
+pub struct StubServer {
+    base_url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl StubServer {
+    pub fn start(routes: Vec<(&'static str, &'static str)>) -> Self {
+        Self::start_with_status("200 OK", routes)
+    }
+
+    // Replies to the matched routes with the given status, e.g. "401 Unauthorized".
+    pub fn start_with_status(
+        status: &'static str,
+        routes: Vec<(&'static str, &'static str)>,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+
+        thread::spawn({
+            let requests = requests.clone();
+            move || {
+                for stream in listener.incoming().flatten() {
+                    Self::handle_connection(stream, status, &routes, &requests);
+                }
+            }
+        });
+
+        Self { base_url, requests }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    // Request targets (path with the query) received so far.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn handle_connection(
+        mut stream: TcpStream,
+        status: &str,
+        routes: &[(&'static str, &'static str)],
+        requests: &Mutex<Vec<String>>,
+    ) {
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).is_err() {
+            return;
+        }
+
+        // skip the headers, requests are expected to have no body
+        let mut header = String::new();
+        while reader.read_line(&mut header).is_ok() && header.trim_end() != "" {
+            header.clear();
+        }
+
+        let target = request_line
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or_default()
+            .to_string();
+        let path = target.split('?').next().unwrap_or_default().to_string();
+        requests.lock().unwrap().push(target);
+
+        let response = match routes.iter().find(|(route, _)| *route == path) {
+            Some((_, body)) => format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            ),
+            None => {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+            }
+        };
+        let _ = stream.write_all(response.as_bytes());
+    }
+}
@@ -0,0 +1,259 @@
+use chrono::{Datelike, Duration, NaiveDate, Utc};
+use std::f64::consts::PI;
+
+use crate::weather::weathercontroller::{
+    CityData, DayWeatherData, ForecastWeatherData, PrecipitationData, TemperatureData,
+    WeatherCondition, WeatherData,
+};
+
+const FORECAST_LENGTH: i64 = 8;
+
+// Small deterministic generator, the same place and day always give the same weather.
+struct SeededRandom(u64);
+
+impl SeededRandom {
+    fn new(city: &CityData, date: NaiveDate) -> Self {
+        let lat = (city.lat * 10_000.0).round() as i64 as u64;
+        let lon = (city.lon * 10_000.0).round() as i64 as u64;
+        let day = date.num_days_from_ce() as u64;
+
+        Self(
+            lat.wrapping_mul(0x9E37_79B9_7F4A_7C15)
+                ^ lon.rotate_left(21)
+                ^ day.wrapping_mul(0xBF58_476D_1CE4_E5B9),
+        )
+    }
+
+    // splitmix64
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut value = self.0;
+        value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        value ^ (value >> 31)
+    }
+
+    fn range(&mut self, min: f64, max: f64) -> f64 {
+        let unit = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        min + (max - min) * unit
+    }
+}
+
+// Solar declination in degrees.
+fn declination(date: NaiveDate) -> f64 {
+    23.44 * (2.0 * PI * (284.0 + date.ordinal() as f64) / 365.0).sin()
+}
+
+// Rough climate: warm at the equator, colder and more seasonal towards the poles.
+fn mean_temperature(city: &CityData, date: NaiveDate) -> f64 {
+    let latitude = city.lat.clamp(-90.0, 90.0);
+    let annual_mean = 27.0 - 0.0065 * latitude * latitude;
+    let amplitude = (0.2 * latitude.abs()).min(12.0);
+
+    // the warmest days are in July on the northern and in January on the southern hemisphere
+    let season = (2.0 * PI * (date.ordinal() as f64 - 200.0) / 365.25).cos();
+    annual_mean + amplitude * season * latitude.signum()
+}
+
+// Sunrise and sunset timestamps, the polar day and night are clamped to the full day.
+fn sun_times(city: &CityData, date: NaiveDate) -> (i64, i64) {
+    let latitude = city.lat.clamp(-89.0, 89.0).to_radians();
+    let hour_angle = (-latitude.tan() * declination(date).to_radians().tan())
+        .clamp(-1.0, 1.0)
+        .acos()
+        .to_degrees();
+
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+    let solar_noon = midnight.timestamp() + ((12.0 - city.lon / 15.0) * 3600.0) as i64;
+    let half_day = (hour_angle / 15.0 * 3600.0) as i64;
+
+    (solar_noon - half_day, solar_noon + half_day)
+}
+
+fn day_weather_data(city: &CityData, date: NaiveDate) -> DayWeatherData {
+    let mut random = SeededRandom::new(city, date);
+
+    let mean = mean_temperature(city, date) + random.range(-3.0, 3.0);
+    let daily_range = random.range(5.0, 11.0);
+    let min = mean - daily_range / 2.0;
+    let max = mean + daily_range / 2.0;
+    let detailed_temperature = TemperatureData {
+        min,
+        max,
+        morning: min + 0.3 * daily_range,
+        day: max - 0.5,
+        evening: min + 0.6 * daily_range,
+        night: min + 0.1 * daily_range,
+    };
+
+    let mut condition = match random.range(0.0, 1.0) {
+        value if value < 0.25 => WeatherCondition::Sunny,
+        value if value < 0.45 => WeatherCondition::PartiallyCloudy,
+        value if value < 0.6 => WeatherCondition::MostlyCloudy,
+        value if value < 0.7 => WeatherCondition::Cloudy,
+        value if value < 0.8 => WeatherCondition::SunnyRainy,
+        value if value < 0.9 => WeatherCondition::Rainy,
+        value if value < 0.95 => WeatherCondition::Stormy,
+        _ => WeatherCondition::Foggy,
+    };
+    let rainy = matches!(
+        condition,
+        WeatherCondition::SunnyRainy | WeatherCondition::Rainy | WeatherCondition::Stormy
+    );
+    if rainy && max < 1.0 {
+        condition = WeatherCondition::Snowy;
+    }
+
+    let (description, cloud_cover, probability, volume) = match condition {
+        WeatherCondition::Sunny => ("clear sky", random.range(0.0, 15.0), 0.0, 0.0),
+        WeatherCondition::PartiallyCloudy => {
+            ("scattered clouds", random.range(20.0, 50.0), 0.1, 0.0)
+        }
+        WeatherCondition::MostlyCloudy => ("broken clouds", random.range(50.0, 85.0), 0.2, 0.0),
+        WeatherCondition::Cloudy => ("overcast clouds", random.range(85.0, 100.0), 0.3, 0.0),
+        WeatherCondition::SunnyRainy => (
+            "light rain",
+            random.range(40.0, 70.0),
+            0.6,
+            random.range(0.5, 4.0),
+        ),
+        WeatherCondition::Rainy => (
+            "moderate rain",
+            random.range(80.0, 100.0),
+            0.9,
+            random.range(4.0, 15.0),
+        ),
+        WeatherCondition::Stormy => (
+            "thunderstorm",
+            random.range(80.0, 100.0),
+            1.0,
+            random.range(5.0, 25.0),
+        ),
+        WeatherCondition::Snowy => (
+            "snow",
+            random.range(80.0, 100.0),
+            0.9,
+            random.range(1.0, 10.0),
+        ),
+        WeatherCondition::Foggy | WeatherCondition::Unknown => {
+            ("fog", random.range(90.0, 100.0), 0.1, 0.0)
+        }
+    };
+    let precipitation = PrecipitationData {
+        probability,
+        rain_volume: if condition == WeatherCondition::Snowy {
+            0.0
+        } else {
+            volume
+        },
+        snow_volume: if condition == WeatherCondition::Snowy {
+            volume
+        } else {
+            0.0
+        },
+    };
+
+    let humidity = (40.0 + cloud_cover * 0.5 + random.range(-5.0, 5.0)).clamp(20.0, 100.0);
+    let wind_speed = random.range(0.5, 9.0);
+    let sun_elevation = 90.0 - (city.lat - declination(date)).abs();
+    let (sunrise, sunset) = sun_times(city, date);
+
+    DayWeatherData {
+        description: description.to_string(),
+        condition: condition.clone(),
+        current_temperature: detailed_temperature.day,
+        detailed_temperature,
+        precipitation,
+        uv_index: (12.0 * sun_elevation.max(0.0).to_radians().sin() * (1.0 - 0.0075 * cloud_cover))
+            .max(0.0),
+
+        feels_like_temperature: max - 0.5 - 0.4 * wind_speed,
+        humidity,
+        pressure: random.range(995.0, 1030.0).round(),
+        dew_point: max - 0.5 - (100.0 - humidity) / 5.0,
+        wind_speed,
+        wind_gust: Some(wind_speed * random.range(1.3, 1.8)),
+        wind_direction: random.range(0.0, 360.0).round(),
+        cloud_cover: cloud_cover.round(),
+        visibility: Some(match condition {
+            WeatherCondition::Foggy => random.range(200.0, 1000.0).round(),
+            _ => 10000.0,
+        }),
+        sunrise: Some(sunrise),
+        sunset: Some(sunset),
+    }
+}
+
+// Plausible current weather and the daily forecast for any place, without a weather provider.
+// The hourly and minutely data are left empty.
+pub fn generate_weather_data(city: &CityData) -> WeatherData {
+    let now = Utc::now();
+    let today = now.date_naive();
+
+    let forecast_data: Vec<ForecastWeatherData> = (0..FORECAST_LENGTH)
+        .map(|day| ForecastWeatherData {
+            timestamp: (now + Duration::days(day)).timestamp(),
+            weather_data: day_weather_data(city, today + Duration::days(day)),
+        })
+        .collect();
+
+    WeatherData {
+        current_data: day_weather_data(city, today),
+        forecast_data,
+        hourly_data: vec![],
+        minutely_data: vec![],
+        // the dummy data follows the time zone of the device
+        utc_offset: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn city(lat: f64, lon: f64) -> CityData {
+        CityData {
+            lat,
+            lon,
+            city_name: String::new(),
+            label: None,
+            note: None,
+        }
+    }
+
+    #[test]
+    fn same_place_and_day_give_the_same_weather() {
+        let date = NaiveDate::from_ymd_opt(2024, 3, 4).unwrap();
+        assert_eq!(
+            day_weather_data(&city(52.52, 13.41), date),
+            day_weather_data(&city(52.52, 13.41), date)
+        );
+        assert_ne!(
+            day_weather_data(&city(52.52, 13.41), date),
+            day_weather_data(&city(48.14, 11.58), date)
+        );
+    }
+
+    #[test]
+    fn temperatures_follow_the_climate() {
+        let january = NaiveDate::from_ymd_opt(2024, 1, 15).unwrap();
+        let july = NaiveDate::from_ymd_opt(2024, 7, 15).unwrap();
+        let reykjavik = city(64.14, -21.9);
+        let singapore = city(1.29, 103.85);
+        let sydney = city(-33.87, 151.21);
+
+        assert!(mean_temperature(&reykjavik, january) < mean_temperature(&singapore, january));
+        assert!(mean_temperature(&reykjavik, january) < mean_temperature(&reykjavik, july));
+        assert!(mean_temperature(&sydney, january) > mean_temperature(&sydney, july));
+    }
+
+    #[test]
+    fn sun_rises_before_it_sets() {
+        let date = NaiveDate::from_ymd_opt(2024, 3, 4).unwrap();
+        let (sunrise, sunset) = sun_times(&city(52.52, 13.41), date);
+
+        assert!(sunrise < sunset);
+        // about 11 hours of daylight in Berlin at the beginning of March
+        assert!((10 * 3600..12 * 3600).contains(&(sunset - sunrise)));
+    }
+}
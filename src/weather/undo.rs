@@ -0,0 +1,90 @@
+use chrono::{DateTime, Duration, Utc};
+
+// How long a removal can be undone, the snackbar offering it is shown for the same time.
+pub const UNDO_TIMEOUT: Duration = Duration::seconds(8);
+
+// Keeps what the last destructive action removed from a list, e.g. a deleted city or all the
+// cities of a cleared list, so it can be put back for a while. The elements are kept with
+// their positions before the removal. Each new action replaces what was kept before.
+pub struct UndoBuffer<T> {
+    removed: Vec<(usize, T)>,
+    expires_at: DateTime<Utc>,
+}
+
+impl<T> UndoBuffer<T> {
+    pub fn new() -> Self {
+        Self {
+            removed: vec![],
+            expires_at: DateTime::<Utc>::MIN_UTC,
+        }
+    }
+
+    pub fn keep(&mut self, removed: Vec<(usize, T)>, now: DateTime<Utc>) {
+        self.removed = removed;
+        self.expires_at = now + UNDO_TIMEOUT;
+    }
+
+    // Puts the kept elements back at their positions, or at the end if the list got shorter
+    // meanwhile. Returns the positions they were restored at, empty if nothing was kept or
+    // the timeout has passed.
+    pub fn restore(&mut self, elements: &mut Vec<T>, now: DateTime<Utc>) -> Vec<usize> {
+        let mut removed = std::mem::take(&mut self.removed);
+        if now >= self.expires_at {
+            return vec![];
+        }
+
+        // in ascending order every element gets the position it had before
+        removed.sort_by_key(|(index, _)| *index);
+        removed
+            .into_iter()
+            .map(|(index, element)| {
+                let index = index.min(elements.len());
+                elements.insert(index, element);
+                index
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_element_is_restored_at_its_position() {
+        let now = Utc::now();
+        let mut elements = vec!["a", "b", "c"];
+        let mut buffer = UndoBuffer::new();
+
+        buffer.keep(vec![(1, elements.remove(1))], now);
+        assert_eq!(buffer.restore(&mut elements, now), [1]);
+        assert_eq!(elements, ["a", "b", "c"]);
+
+        // only restored once
+        assert!(buffer.restore(&mut elements, now).is_empty());
+        assert_eq!(elements, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn cleared_list_is_restored() {
+        let now = Utc::now();
+        let mut elements = vec!["a", "b", "c"];
+        let mut buffer = UndoBuffer::new();
+
+        buffer.keep(elements.drain(..).enumerate().rev().collect(), now);
+        elements.push("d");
+        assert_eq!(buffer.restore(&mut elements, now), [0, 1, 2]);
+        assert_eq!(elements, ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn nothing_is_restored_after_the_timeout() {
+        let now = Utc::now();
+        let mut elements = vec!["a", "b"];
+        let mut buffer = UndoBuffer::new();
+
+        buffer.keep(vec![(0, elements.remove(0))], now);
+        assert!(buffer.restore(&mut elements, now + UNDO_TIMEOUT).is_empty());
+        assert_eq!(elements, ["b"]);
+    }
+}
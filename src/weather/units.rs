@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+
+// Weather data is stored in metric units (°C, mm, m/s) and converted only for display.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum TemperatureUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum PrecipitationUnit {
+    #[default]
+    Millimeters,
+    Inches,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum WindSpeedUnit {
+    #[default]
+    KilometersPerHour,
+    MilesPerHour,
+    MetersPerSecond,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub struct Units {
+    #[serde(default)]
+    pub temperature: TemperatureUnit,
+    #[serde(default)]
+    pub precipitation: PrecipitationUnit,
+    #[serde(default)]
+    pub wind_speed: WindSpeedUnit,
+}
+
+impl TemperatureUnit {
+    pub fn convert(&self, celsius: f64) -> f64 {
+        match self {
+            TemperatureUnit::Celsius => celsius,
+            TemperatureUnit::Fahrenheit => celsius * 9.0 / 5.0 + 32.0,
+        }
+    }
+
+    #[cfg_attr(any(target_os = "android", target_arch = "wasm32"), allow(dead_code))]
+    pub fn label(&self) -> &'static str {
+        match self {
+            TemperatureUnit::Celsius => "°C",
+            TemperatureUnit::Fahrenheit => "°F",
+        }
+    }
+}
+
+impl PrecipitationUnit {
+    pub fn convert(&self, millimeters: f64) -> f64 {
+        match self {
+            PrecipitationUnit::Millimeters => millimeters,
+            PrecipitationUnit::Inches => millimeters / 25.4,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PrecipitationUnit::Millimeters => "mm",
+            PrecipitationUnit::Inches => "in",
+        }
+    }
+}
+
+impl WindSpeedUnit {
+    pub fn convert(&self, meters_per_second: f64) -> f64 {
+        match self {
+            WindSpeedUnit::KilometersPerHour => meters_per_second * 3.6,
+            WindSpeedUnit::MilesPerHour => meters_per_second * 2.236_936,
+            WindSpeedUnit::MetersPerSecond => meters_per_second,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            WindSpeedUnit::KilometersPerHour => "km/h",
+            WindSpeedUnit::MilesPerHour => "mph",
+            WindSpeedUnit::MetersPerSecond => "m/s",
+        }
+    }
+}
+
+// Visibility and pressure have no setting of their own, they follow the precipitation unit.
+impl Units {
+    fn imperial(&self) -> bool {
+        self.precipitation == PrecipitationUnit::Inches
+    }
+
+    pub fn convert_visibility(&self, meters: f64) -> f64 {
+        match self.imperial() {
+            true => meters / 1609.344,
+            false => meters / 1000.0,
+        }
+    }
+
+    pub fn visibility_label(&self) -> &'static str {
+        match self.imperial() {
+            true => "mi",
+            false => "km",
+        }
+    }
+
+    pub fn convert_pressure(&self, hectopascals: f64) -> f64 {
+        match self.imperial() {
+            true => hectopascals / 33.863_886,
+            false => hectopascals,
+        }
+    }
+
+    pub fn pressure_label(&self) -> &'static str {
+        match self.imperial() {
+            true => "inHg",
+            false => "hPa",
+        }
+    }
+}
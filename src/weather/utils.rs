@@ -1,9 +1,241 @@
-use chrono::{DateTime, Datelike};
+use chrono::{DateTime, Datelike, FixedOffset, Local, Utc};
+use std::path::PathBuf;
 
-pub fn get_day_from_datetime(date: DateTime<chrono::offset::Utc>) -> String {
-    if date.day() == chrono::offset::Local::now().day() {
-        // TODO: translations
-        return "Today".to_string();
+use crate::weather::locale::current_locale;
+use crate::weather::weathercontroller::MinutelyWeatherData;
+
+#[cfg(target_os = "android")]
+use std::env;
+
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+const ORGANIZATION_QUALIFIER: &str = "com"; // have to match android app name in cargo.toml
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+const ORGANIZATION_NAME: &str = "felgo.demos"; // have to match android app name in cargo.toml
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+const APPLICATION_NAME: &str = "rustyweather"; // have to match app android name in cargo.toml
+
+// precipitation intensity (mm/h) considered as actual rain or snow
+const NOWCAST_PRECIPITATION_THRESHOLD: f64 = 0.1;
+
+pub fn project_data_dir() -> Option<PathBuf> {
+    #[cfg(target_os = "android")]
+    {
+        match env::var("ANDROID_DATA") {
+            Ok(data_root) => {
+                if data_root.is_empty() {
+                    return None;
+                } else {
+                    let project_name = format!(
+                        "{}.{}.{}",
+                        ORGANIZATION_QUALIFIER, ORGANIZATION_NAME, APPLICATION_NAME
+                    );
+                    return Some(PathBuf::from(format!(
+                        "{}/data/{}/files",
+                        data_root, project_name
+                    )));
+                }
+            }
+            Err(_e) => {
+                log::warn!("Cannot read ANDROID_DATA, persistence not avaialble.");
+                return None;
+            }
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
+    {
+        if let Some(project_dir) = directories::ProjectDirs::from(
+            ORGANIZATION_QUALIFIER,
+            ORGANIZATION_NAME,
+            APPLICATION_NAME,
+        ) {
+            return Some(project_dir.data_dir().to_path_buf());
+        };
+
+        None
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        None
+    }
+}
+
+// Directory the files are exported to on the desktop, the downloads of the user.
+// Android saves them with the media store, see androidstorage.rs.
+#[cfg(all(not(target_arch = "wasm32"), not(target_os = "android")))]
+pub fn export_dir() -> Option<PathBuf> {
+    let user_dirs = directories::UserDirs::new()?;
+    Some(
+        user_dirs
+            .download_dir()
+            .unwrap_or(user_dirs.home_dir())
+            .to_path_buf(),
+    )
+}
+
+// Time in the city with the given offset from UTC in seconds, reported by the weather provider.
+// The time zone of the device is used when the offset is not known, e.g. for the dummy data.
+pub fn city_datetime_from_timestamp(
+    timestamp: i64,
+    utc_offset: Option<i32>,
+) -> Option<DateTime<FixedOffset>> {
+    let date = DateTime::from_timestamp(timestamp, 0)?;
+    match utc_offset {
+        Some(utc_offset) => Some(date.with_timezone(&FixedOffset::east_opt(utc_offset)?)),
+        None => Some(date.with_timezone(&Local).fixed_offset()),
+    }
+}
+
+pub fn get_day_name_from_timestamp(timestamp: i64, utc_offset: Option<i32>) -> String {
+    let Some(date) = city_datetime_from_timestamp(timestamp, utc_offset) else {
+        return String::new();
+    };
+
+    let locale = current_locale();
+    if date.date_naive() == Utc::now().with_timezone(date.offset()).date_naive() {
+        return locale.today_label().to_string();
+    }
+    locale.short_weekday_name(date.weekday()).to_string()
+}
+
+pub fn get_time_from_timestamp(timestamp: i64, utc_offset: Option<i32>) -> String {
+    match city_datetime_from_timestamp(timestamp, utc_offset) {
+        Some(date) => date.format("%H:%M").to_string(),
+        None => String::new(),
+    }
+}
+
+pub fn get_day_time_from_timestamp(timestamp: i64, utc_offset: Option<i32>) -> String {
+    match city_datetime_from_timestamp(timestamp, utc_offset) {
+        Some(date) => format!(
+            "{} {}",
+            current_locale().short_weekday_name(date.weekday()),
+            date.format("%H:%M")
+        ),
+        None => String::new(),
+    }
+}
+
+pub enum PrecipitationNowcast {
+    Starting(i64), // minutes from now
+    Stopping(i64), // minutes from now
+    Continuing,
+}
+
+// Skips the samples of the minutes already passed, each sample covers the minute starting at its
+// timestamp.
+pub fn upcoming_minutely_data(
+    minutely_data: &[MinutelyWeatherData],
+    now: i64,
+) -> Vec<&MinutelyWeatherData> {
+    minutely_data
+        .iter()
+        .filter(|data| data.timestamp + 60 > now)
+        .collect()
+}
+
+// The minutes are counted from now. There is no nowcast once the whole forecast window is in the
+// past.
+pub fn get_precipitation_nowcast(
+    minutely_data: &[MinutelyWeatherData],
+    now: i64,
+) -> Option<PrecipitationNowcast> {
+    let upcoming = upcoming_minutely_data(minutely_data, now);
+    let first = upcoming.first()?;
+    let is_precipitation =
+        |data: &MinutelyWeatherData| data.precipitation >= NOWCAST_PRECIPITATION_THRESHOLD;
+    let minutes_from_now = |data: &MinutelyWeatherData| (data.timestamp - now).max(0) / 60;
+
+    if is_precipitation(first) {
+        match upcoming.iter().find(|data| !is_precipitation(data)) {
+            Some(data) => Some(PrecipitationNowcast::Stopping(minutes_from_now(data))),
+            None => Some(PrecipitationNowcast::Continuing),
+        }
+    } else {
+        upcoming
+            .iter()
+            .find(|data| is_precipitation(data))
+            .map(|data| PrecipitationNowcast::Starting(minutes_from_now(data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minutely_data(start: i64, precipitation: &[f64]) -> Vec<MinutelyWeatherData> {
+        precipitation
+            .iter()
+            .enumerate()
+            .map(|(minute, precipitation)| MinutelyWeatherData {
+                timestamp: start + minute as i64 * 60,
+                precipitation: *precipitation,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn passed_minutes_are_skipped() {
+        let start = 1_700_000_040;
+        let data = minutely_data(start, &[0.1, 0.2, 0.3, 0.4]);
+
+        let upcoming = upcoming_minutely_data(&data, start + 90);
+        assert_eq!(upcoming.len(), 3);
+        assert_eq!(upcoming[0].precipitation, 0.2);
+        assert!(upcoming_minutely_data(&data, start + 240).is_empty());
+    }
+
+    #[test]
+    fn nowcast_is_relative_to_now() {
+        let start = 1_700_000_040;
+        let data = minutely_data(start, &[0.0, 0.0, 0.0, 0.0, 0.5, 0.5, 0.0]);
+
+        let Some(PrecipitationNowcast::Starting(minutes)) = get_precipitation_nowcast(&data, start)
+        else {
+            panic!("precipitation expected to start");
+        };
+        assert_eq!(minutes, 4);
+
+        // three minutes later, in the middle of the fourth minute
+        let now = start + 3 * 60 + 30;
+        let Some(PrecipitationNowcast::Starting(minutes)) = get_precipitation_nowcast(&data, now)
+        else {
+            panic!("precipitation expected to start");
+        };
+        assert_eq!(minutes, 0);
+
+        let now = start + 5 * 60;
+        let Some(PrecipitationNowcast::Stopping(minutes)) = get_precipitation_nowcast(&data, now)
+        else {
+            panic!("precipitation expected to stop");
+        };
+        assert_eq!(minutes, 1);
+    }
+
+    #[test]
+    fn time_is_shown_in_the_city() {
+        // 2024-01-15 23:30 UTC
+        let timestamp = 1_705_361_400;
+        assert_eq!(get_time_from_timestamp(timestamp, Some(0)), "23:30");
+        assert_eq!(get_time_from_timestamp(timestamp, Some(3600)), "00:30");
+        assert_eq!(get_time_from_timestamp(timestamp, Some(-5 * 3600)), "18:30");
+        assert_eq!(
+            get_time_from_timestamp(timestamp, Some(5 * 3600 + 1800)),
+            "05:00"
+        );
+    }
+
+    #[test]
+    fn no_nowcast_after_the_window() {
+        let start = 1_700_000_040;
+        let data = minutely_data(start, &[0.5, 0.5, 0.5]);
+
+        assert!(matches!(
+            get_precipitation_nowcast(&data, start + 2 * 60),
+            Some(PrecipitationNowcast::Continuing)
+        ));
+        assert!(get_precipitation_nowcast(&data, start + 3 * 60).is_none());
+        assert!(get_precipitation_nowcast(&[], start).is_none());
     }
-    date.weekday().to_string()
 }
@@ -1,11 +1,45 @@
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
 use serde::{Deserialize, Serialize};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, RwLock};
+
+use crate::weather::gazetteer;
+use crate::weather::undo::UndoBuffer;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct CityData {
     pub lat: f64,
     pub lon: f64,
+    // as returned by the geocoder, the label and the note are set by the user
     pub city_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+// Places closer than this, in degrees of latitude and longitude, are the same place (about 10 m).
+const SAME_PLACE_TOLERANCE: f64 = 0.0001;
+
+impl CityData {
+    // Compares only the coordinates, the same place can be named differently.
+    pub fn is_same_place(&self, other: &CityData) -> bool {
+        (self.lat - other.lat).abs() < SAME_PLACE_TOLERANCE
+            && (self.lon - other.lon).abs() < SAME_PLACE_TOLERANCE
+    }
+
+    // Blank values clear the label or the note.
+    pub fn annotate(&mut self, label: Option<String>, note: Option<String>) {
+        self.label = label
+            .map(|label| label.trim().to_string())
+            .filter(|label| !label.is_empty());
+        self.note = note.filter(|note| !note.trim().is_empty());
+    }
+
+    // The label of the user if set, otherwise the geocoded name.
+    pub fn display_name(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.city_name)
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
@@ -50,24 +84,143 @@ pub struct DayWeatherData {
 
     pub precipitation: PrecipitationData,
     pub uv_index: f64,
+
+    #[serde(default)]
+    pub feels_like_temperature: f64,
+    #[serde(default)]
+    pub humidity: f64, // %
+    #[serde(default)]
+    pub pressure: f64, // hPa
+    #[serde(default)]
+    pub dew_point: f64,
+    #[serde(default)]
+    pub wind_speed: f64, // m/s
+    #[serde(default)]
+    pub wind_gust: Option<f64>, // m/s
+    #[serde(default)]
+    pub wind_direction: f64, // degrees
+    #[serde(default)]
+    pub cloud_cover: f64, // %
+    #[serde(default)]
+    pub visibility: Option<f64>, // m
+    #[serde(default)]
+    pub sunrise: Option<i64>,
+    #[serde(default)]
+    pub sunset: Option<i64>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
 pub struct ForecastWeatherData {
-    pub day_name: String,
+    pub timestamp: i64,
     pub weather_data: DayWeatherData,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct HourlyWeatherData {
+    pub timestamp: i64,
+    pub condition: WeatherCondition,
+    pub description: String,
+
+    pub temperature: f64,
+    pub precipitation: PrecipitationData,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct MinutelyWeatherData {
+    pub timestamp: i64,
+    pub precipitation: f64, // mm/h
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct WeatherData {
     pub current_data: DayWeatherData,
     pub forecast_data: Vec<ForecastWeatherData>,
+    #[serde(default)]
+    pub hourly_data: Vec<HourlyWeatherData>,
+    #[serde(default)]
+    pub minutely_data: Vec<MinutelyWeatherData>,
+    // offset of the local time of the city from UTC in seconds, the times are shown in it
+    #[serde(default)]
+    pub utc_offset: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum WeatherAlertSeverity {
+    #[default]
+    Minor,
+    Moderate,
+    Severe,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct WeatherAlert {
+    pub sender: String,
+    pub event: String,
+    pub start: i64,
+    pub end: i64,
+    pub description: String,
+    pub tags: Vec<String>,
+}
+
+impl WeatherAlert {
+    // Providers do not report a severity level, so it is estimated from the event name.
+    pub fn severity(&self) -> WeatherAlertSeverity {
+        let event = self.event.to_lowercase();
+        if event.contains("warning") || event.contains("emergency") {
+            WeatherAlertSeverity::Severe
+        } else if event.contains("watch") || event.contains("advisory") {
+            WeatherAlertSeverity::Moderate
+        } else {
+            WeatherAlertSeverity::Minor
+        }
+    }
+}
+
+// Result of the last weather update of a city. Only the real weather providers can fail.
+#[derive(Clone, Debug, PartialEq, Default)]
+#[cfg_attr(not(weather_provider), allow(dead_code))]
+pub enum RefreshStatus {
+    #[default]
+    Fresh,
+    // the update failed, the previously fetched data is kept
+    Stale(String),
+    // the update failed and there is no data to show
+    Failed(String),
+}
+
+impl RefreshStatus {
+    #[cfg(weather_provider)]
+    pub fn from_error(error: &dyn std::error::Error, has_data: bool) -> Self {
+        if has_data {
+            RefreshStatus::Stale(error.to_string())
+        } else {
+            RefreshStatus::Failed(error.to_string())
+        }
+    }
+}
+
+// Result of loading the stored cities.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoadStatus {
+    Loaded,
+    // the stored data was damaged, the cities were restored from a backup
+    Recovered,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct CityWeatherData {
     pub city_data: CityData,
     pub weather_data: WeatherData,
+    #[serde(default)]
+    pub alerts: Vec<WeatherAlert>,
+    #[serde(skip)]
+    pub refresh_status: RefreshStatus,
+    // when the data was fetched from the provider
+    #[serde(default)]
+    pub fetched_at: Option<i64>,
+    // time of the current conditions reported by the provider
+    #[serde(default)]
+    pub observed_at: Option<i64>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -77,36 +230,130 @@ pub struct GeoLocationData {
     pub lon: f64,
     pub country: String,
     pub state: Option<String>,
+    // IANA time zone, when known to the geocoding
+    #[serde(default)]
+    pub timezone: Option<String>,
 }
 
-#[cfg(not(target_arch = "wasm32"))]
-pub type WeatherControllerPointer = Box<dyn WeatherController + Send>;
-#[cfg(target_arch = "wasm32")]
-pub type WeatherControllerPointer = Box<dyn WeatherController + Send + 'static>;
+// Number of cities updated at the same time, unless configured otherwise.
+#[cfg(weather_provider)]
+pub const DEFAULT_REFRESH_CONCURRENCY: usize = 4;
+
+pub type WeatherControllerPointer = Arc<dyn WeatherController + Send + Sync>;
 
-pub type WeatherControllerSharedPointer = Arc<Mutex<WeatherControllerPointer>>;
+// Moves the element like a drag and drop, e.g. [a, b, c, d] with 0 -> 2 gives [b, c, a, d].
+pub fn move_element<T>(
+    elements: &mut Vec<T>,
+    index: usize,
+    new_index: usize,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if index >= elements.len() || new_index >= elements.len() {
+        return Err(index_out_of_bounds());
+    }
+
+    let element = elements.remove(index);
+    elements.insert(new_index, element);
+    Ok(())
+}
 
+// Removes the element and keeps it in the undo buffer, to be restored by undo_remove.
+pub fn remove_element<T>(
+    elements: &mut Vec<T>,
+    index: usize,
+    removed: &mut UndoBuffer<T>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if index >= elements.len() {
+        return Err(index_out_of_bounds());
+    }
+
+    removed.keep(vec![(index, elements.remove(index))], Utc::now());
+    Ok(())
+}
+
+pub fn index_out_of_bounds() -> Box<dyn std::error::Error + Send + Sync> {
+    Box::new(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        "Index out of bounds",
+    ))
+}
+
+// The controller can be replaced at runtime, e.g. after an api key is entered. The lock only
+// guards the replacement, the controllers synchronize their own state.
+pub type WeatherControllerSharedPointer = Arc<RwLock<WeatherControllerPointer>>;
+
+// The network requests are async, all the other calls only work with the data in memory or on
+// the disk. The futures are not Send on wasm, where they run on the browser event loop.
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
 pub trait WeatherController {
-    fn load(&mut self) -> Result<(), Box<dyn std::error::Error>>;
-    fn save(&self) -> Result<(), Box<dyn std::error::Error>>;
+    fn load(&self) -> Result<LoadStatus, Box<dyn std::error::Error + Send + Sync>>;
+    fn save(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
 
-    fn refresh_cities(&mut self) -> Result<Vec<CityWeatherData>, Box<dyn std::error::Error>>;
+    // Cities that could not be updated are returned with a stale or failed refresh status.
+    async fn refresh_cities(
+        &self,
+    ) -> Result<Vec<CityWeatherData>, Box<dyn std::error::Error + Send + Sync>>;
 
-    fn add_city(
-        &mut self,
+    // Only the cities with data older than max_age are fetched, the others come from the cache.
+    async fn refresh_outdated_cities(
+        &self,
+        _max_age: Duration,
+    ) -> Result<Vec<CityWeatherData>, Box<dyn std::error::Error + Send + Sync>> {
+        self.refresh_cities().await
+    }
+
+    // Returns the last known data without fetching new weather.
+    fn cached_cities(
+        &self,
+    ) -> Result<Vec<CityWeatherData>, Box<dyn std::error::Error + Send + Sync>>;
+
+    async fn add_city(
+        &self,
         city: CityData,
-    ) -> Result<Option<CityWeatherData>, Box<dyn std::error::Error>>;
+    ) -> Result<Option<CityWeatherData>, Box<dyn std::error::Error + Send + Sync>>;
 
+    // Moves the city at index to new_index, the cities in between shift by one position.
     fn reorder_cities(
-        &mut self,
+        &self,
         index: usize,
         new_index: usize,
-    ) -> Result<(), Box<dyn std::error::Error>>;
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    // The removed city is kept with its weather for UNDO_TIMEOUT, see undo_remove.
+    fn remove_city(&self, index: usize) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
 
-    fn remove_city(&mut self, index: usize) -> Result<(), Box<dyn std::error::Error>>;
+    // Puts the cities of the last removal back at their positions if it was within
+    // UNDO_TIMEOUT. Returns the positions of the restored cities, empty if there were none.
+    fn undo_remove(&self) -> Result<Vec<usize>, Box<dyn std::error::Error + Send + Sync>>;
 
-    fn search_location(
+    // Sets the label and the note of the user, None clears them. The city is still matched
+    // by its coordinates, e.g. on refresh.
+    fn annotate_city(
+        &self,
+        index: usize,
+        label: Option<String>,
+        note: Option<String>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    // Replaces the whole list, e.g. on import. The cities already in the list keep their
+    // weather and take the new name, the others have no weather until they are refreshed.
+    fn set_cities(
+        &self,
+        cities: Vec<CityData>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    async fn search_location(
         &self,
         query: String,
-    ) -> Result<Vec<GeoLocationData>, Box<dyn std::error::Error>>;
+    ) -> Result<Vec<GeoLocationData>, Box<dyn std::error::Error + Send + Sync>>;
+
+    // The named place at the coordinates, None if there is no place nearby.
+    // Looked up in the bundled gazetteer unless the provider offers reverse geocoding.
+    async fn reverse_geocode(
+        &self,
+        lat: f64,
+        lon: f64,
+    ) -> Result<Option<GeoLocationData>, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(gazetteer::reverse_geocode(lat, lon))
+    }
 }
@@ -1,25 +1,49 @@
+use chrono::Duration;
 use slint::{ComponentHandle, Model, ModelRc, SharedString, VecModel, Weak};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
 use crate::ui;
 use ui::{
-    AppWindow, BusyLayerController, CityWeather, CityWeatherInfo, GeoLocation, GeoLocationEntry,
-    IconType, TemperatureInfo, WeatherForecastInfo, WeatherInfo,
+    AlertSeverity, AppWindow, BusyLayerController, CityListImport, CityListSettings, CityWeather,
+    CityWeatherInfo, ExportFormat, GeoLocation, GeoLocationEntry, HourlyForecastInfo, IconType,
+    ImportSource, Language, LanguageSettings, NowcastInfo, NowcastKind, PrecipitationUnit,
+    RefreshInfo, TemperatureInfo, TemperatureUnit, UnitSettings, WeatherAlertInfo,
+    WeatherForecastInfo, WeatherInfo, WindSpeedUnit,
 };
 
+use crate::weather::citylist;
+use crate::weather::coordinates::{locate_coordinates, location_at, parse_coordinates};
+use crate::weather::export;
+use crate::weather::gazetteer;
+use crate::weather::locale::Locale;
+use crate::weather::refreshscheduler::RefreshScheduler;
+use crate::weather::runtime::spawn_task;
+use crate::weather::undo::UNDO_TIMEOUT;
+use crate::weather::units;
+use crate::weather::units::Units;
+
+use crate::weather::utils::*;
 use crate::weather::weathercontroller::{
-    CityData, CityWeatherData, DayWeatherData, ForecastWeatherData, GeoLocationData,
-    WeatherCondition, WeatherControllerSharedPointer,
+    index_out_of_bounds, CityData, CityWeatherData, DayWeatherData, ForecastWeatherData,
+    GeoLocationData, HourlyWeatherData, LoadStatus, RefreshStatus, WeatherAlert,
+    WeatherAlertSeverity, WeatherCondition, WeatherControllerPointer,
+    WeatherControllerSharedPointer,
 };
 
-#[cfg(not(target_arch = "wasm32"))]
-use async_std::task::spawn as spawn_task;
+// How long a city has to be held before it can be dragged.
+const LONG_PRESS_DURATION: std::time::Duration = std::time::Duration::from_millis(500);
 
-#[cfg(target_arch = "wasm32")]
-use wasm_bindgen_futures::spawn_local as spawn_task;
+// How long the typing has to pause before the geocoding api is asked.
+const SEARCH_DELAY: std::time::Duration = std::time::Duration::from_millis(400);
 
+#[derive(Clone)]
 pub struct WeatherDisplayController {
     data_controller: WeatherControllerSharedPointer,
+    refresh_scheduler: Arc<Mutex<RefreshScheduler>>,
+    // locations are searched only in the bundled gazetteer, without the provider's geocoding
+    offline_search: bool,
 }
 
 fn forecast_graph_command(
@@ -109,13 +133,19 @@ fn forecast_graph_command(
 }
 
 impl WeatherDisplayController {
-    pub fn new(data_controller: &WeatherControllerSharedPointer) -> Self {
+    pub fn new(
+        data_controller: &WeatherControllerSharedPointer,
+        cache_ttl: Duration,
+        offline_search: bool,
+    ) -> Self {
         Self {
             data_controller: data_controller.clone(),
+            refresh_scheduler: Arc::new(Mutex::new(RefreshScheduler::new(cache_ttl))),
+            offline_search,
         }
     }
 
-    pub fn initialize_ui(&self, window: &AppWindow, support_add_city: bool) {
+    pub fn initialize_ui(&self, window: &AppWindow) {
         let city_weather = window.global::<CityWeather>();
         let geo_location = window.global::<GeoLocation>();
 
@@ -127,8 +157,9 @@ impl WeatherDisplayController {
             vec![],
         ))));
 
-        // initialize state
-        city_weather.set_can_add_city(support_add_city);
+        // the data is highlighted once it missed a scheduled refresh
+        let cache_ttl = self.refresh_scheduler.lock().unwrap().cache_ttl();
+        city_weather.set_outdated_after_minutes((cache_ttl.num_minutes() * 2) as i32);
 
         // handle callbacks
         city_weather.on_get_forecast_graph_command(forecast_graph_command);
@@ -136,8 +167,9 @@ impl WeatherDisplayController {
         city_weather.on_refresh_all({
             let window_weak = window.as_weak();
             let data_controller = self.data_controller.clone();
+            let refresh_scheduler = self.refresh_scheduler.clone();
 
-            move || Self::refresh_cities(&window_weak, &data_controller)
+            move || Self::refresh_cities(&window_weak, &data_controller, &refresh_scheduler)
         });
 
         city_weather.on_reorder({
@@ -158,22 +190,178 @@ impl WeatherDisplayController {
             }
         });
 
+        // a city is dragged after it was held for a while, the timer is restarted on each press
+        let long_press_timer = Rc::new(slint::Timer::default());
+
+        city_weather.on_start_long_press({
+            let window_weak = window.as_weak();
+            let long_press_timer = long_press_timer.clone();
+
+            move || {
+                let window_weak = window_weak.clone();
+                long_press_timer.start(
+                    slint::TimerMode::SingleShot,
+                    LONG_PRESS_DURATION,
+                    move || {
+                        if let Some(window) = window_weak.upgrade() {
+                            window.global::<CityWeather>().set_long_pressed(true);
+                        }
+                    },
+                );
+            }
+        });
+
+        city_weather.on_cancel_long_press({
+            let window_weak = window.as_weak();
+
+            move || {
+                long_press_timer.stop();
+                if let Some(window) = window_weak.upgrade() {
+                    window.global::<CityWeather>().set_long_pressed(false);
+                }
+            }
+        });
+
+        city_weather.on_annotate({
+            let window_weak = window.as_weak();
+            let data_controller = self.data_controller.clone();
+
+            move |index, label, note| {
+                if let Err(e) =
+                    Self::annotate_city(&window_weak, &data_controller, index, label, note)
+                {
+                    log::warn!("Failed to set the label of the city at {}: {}", index, e);
+                }
+            }
+        });
+
+        // hides the undo of the last deletion after the controller stops keeping the city
+        let undo_timer = Rc::new(slint::Timer::default());
+
         city_weather.on_delete({
             let window_weak = window.as_weak();
             let data_controller = self.data_controller.clone();
+            let undo_timer = undo_timer.clone();
+
+            move |index| match Self::remove_city(&window_weak, &data_controller, index) {
+                Ok(city_name) => {
+                    let window = window_weak.upgrade().unwrap();
+                    let city_weather = window.global::<CityWeather>();
+                    city_weather.set_deleted_city(city_name);
+                    city_weather.set_can_undo_delete(true);
+
+                    let window_weak = window_weak.clone();
+                    undo_timer.start(
+                        slint::TimerMode::SingleShot,
+                        UNDO_TIMEOUT.to_std().unwrap_or_default(),
+                        move || {
+                            if let Some(window) = window_weak.upgrade() {
+                                window.global::<CityWeather>().set_can_undo_delete(false);
+                            }
+                        },
+                    );
+                }
+                Err(e) => log::warn!("Failed to remove city from {}: {}", index, e),
+            }
+        });
+
+        city_weather.on_undo_delete({
+            let window_weak = window.as_weak();
+            let data_controller = self.data_controller.clone();
 
-            move |index| {
-                if let Err(e) = Self::remove_city(&window_weak, &data_controller, index) {
-                    log::warn!("Failed to remove city from {}: {}", index, e);
+            move || {
+                undo_timer.stop();
+                if let Err(e) = Self::undo_remove(&window_weak, &data_controller) {
+                    log::warn!("Failed to restore the removed city: {}", e);
                 }
             }
         });
 
+        city_weather.on_export_forecast({
+            let window_weak = window.as_weak();
+            let data_controller = self.data_controller.clone();
+
+            move |index, format| match Self::export_forecast(
+                &window_weak,
+                &data_controller,
+                index,
+                format,
+            ) {
+                Ok(location) => SharedString::from(location),
+                Err(e) => {
+                    log::warn!("Failed to export the forecast of city {}: {}", index, e);
+                    SharedString::new()
+                }
+            }
+        });
+
+        let city_list_settings = window.global::<CityListSettings>();
+        city_list_settings.set_file_location(citylist::city_list_location().into());
+        // Android imports the file it exports, the browser cannot read a path
+        city_list_settings.set_import_source(if cfg!(target_arch = "wasm32") {
+            ImportSource::FileChooser
+        } else if cfg!(target_os = "android") {
+            ImportSource::FileLocation
+        } else {
+            ImportSource::EnteredPath
+        });
+        city_list_settings.set_import_path(citylist::city_list_location().into());
+
+        city_list_settings.on_export_list({
+            let data_controller = self.data_controller.clone();
+
+            move || {
+                let data_controller = Self::current_controller(&data_controller);
+                match citylist::save_city_list(&data_controller) {
+                    Ok(location) => SharedString::from(location),
+                    Err(e) => {
+                        log::warn!("Failed to export the city list: {}", e);
+                        SharedString::new()
+                    }
+                }
+            }
+        });
+
+        city_list_settings.on_import_list({
+            let window_weak = window.as_weak();
+            let display_controller = self.clone();
+
+            move |replace| {
+                let window = window_weak.upgrade().unwrap();
+                display_controller.import_city_list(&window, replace);
+            }
+        });
+
+        // the bundled cities are listed right away, the api is asked only when the typing pauses
+        let search_timer = slint::Timer::default();
+        let search_generation = Arc::new(AtomicU64::new(0));
+
         geo_location.on_search_location({
             let window_weak = window.as_weak();
             let data_controller = self.data_controller.clone();
+            let offline_search = self.offline_search;
 
-            move |location| Self::search_location(&window_weak, &data_controller, location)
+            move |location| {
+                let generation = search_generation.fetch_add(1, Ordering::SeqCst) + 1;
+                Self::search_location_offline(&window_weak, location.clone());
+                if offline_search || location.trim().is_empty() {
+                    search_timer.stop();
+                    return;
+                }
+
+                let window_weak = window_weak.clone();
+                let data_controller = data_controller.clone();
+                let search_generation = search_generation.clone();
+                search_timer.start(slint::TimerMode::SingleShot, SEARCH_DELAY, move || {
+                    Self::search_location(
+                        &window_weak,
+                        &data_controller,
+                        location.clone(),
+                        &search_generation,
+                        generation,
+                    );
+                });
+            }
         });
 
         geo_location.on_add_location({
@@ -186,41 +374,188 @@ impl WeatherDisplayController {
         });
     }
 
-    #[cfg_attr(not(target_os = "android"), allow(dead_code))]
+    // The data age is displayed relative to this time, it has to be updated periodically.
+    pub fn update_current_minute(window: &AppWindow) {
+        let current_minute = chrono::Utc::now().timestamp() / 60;
+        window
+            .global::<CityWeather>()
+            .set_current_minute(current_minute as i32);
+    }
+
+    pub fn set_units(window: &AppWindow, units: &Units) {
+        let unit_settings = window.global::<UnitSettings>();
+
+        unit_settings.set_temperature(match units.temperature {
+            units::TemperatureUnit::Celsius => TemperatureUnit::Celsius,
+            units::TemperatureUnit::Fahrenheit => TemperatureUnit::Fahrenheit,
+        });
+        unit_settings.set_precipitation(match units.precipitation {
+            units::PrecipitationUnit::Millimeters => PrecipitationUnit::Millimeters,
+            units::PrecipitationUnit::Inches => PrecipitationUnit::Inches,
+        });
+        unit_settings.set_wind_speed(match units.wind_speed {
+            units::WindSpeedUnit::KilometersPerHour => WindSpeedUnit::KilometersPerHour,
+            units::WindSpeedUnit::MilesPerHour => WindSpeedUnit::MilesPerHour,
+            units::WindSpeedUnit::MetersPerSecond => WindSpeedUnit::MetersPerSecond,
+        });
+
+        unit_settings.set_precipitation_label(units.precipitation.label().into());
+        unit_settings.set_wind_speed_label(units.wind_speed.label().into());
+        unit_settings.set_visibility_label(units.visibility_label().into());
+        unit_settings.set_pressure_label(units.pressure_label().into());
+    }
+
+    pub fn units_from_ui(window: &AppWindow) -> Units {
+        let unit_settings = window.global::<UnitSettings>();
+
+        Units {
+            temperature: match unit_settings.get_temperature() {
+                TemperatureUnit::Celsius => units::TemperatureUnit::Celsius,
+                TemperatureUnit::Fahrenheit => units::TemperatureUnit::Fahrenheit,
+            },
+            precipitation: match unit_settings.get_precipitation() {
+                PrecipitationUnit::Millimeters => units::PrecipitationUnit::Millimeters,
+                PrecipitationUnit::Inches => units::PrecipitationUnit::Inches,
+            },
+            wind_speed: match unit_settings.get_wind_speed() {
+                WindSpeedUnit::KilometersPerHour => units::WindSpeedUnit::KilometersPerHour,
+                WindSpeedUnit::MilesPerHour => units::WindSpeedUnit::MilesPerHour,
+                WindSpeedUnit::MetersPerSecond => units::WindSpeedUnit::MetersPerSecond,
+            },
+        }
+    }
+
+    pub fn set_locale(window: &AppWindow, locale: Option<Locale>) {
+        window
+            .global::<LanguageSettings>()
+            .set_language(match locale {
+                None => Language::System,
+                Some(Locale::English) => Language::English,
+                Some(Locale::German) => Language::German,
+                Some(Locale::Russian) => Language::Russian,
+            });
+    }
+
+    // None means the system locale.
+    pub fn locale_from_ui(window: &AppWindow) -> Option<Locale> {
+        match window.global::<LanguageSettings>().get_language() {
+            Language::System => None,
+            Language::English => Some(Locale::English),
+            Language::German => Some(Locale::German),
+            Language::Russian => Some(Locale::Russian),
+        }
+    }
+
+    // Displays the already fetched data again, e.g. after units change.
+    pub fn redisplay(&self, window: &AppWindow) {
+        let window_weak = window.as_weak();
+        let data_controller = Self::current_controller(&self.data_controller);
+
+        spawn_task(async move {
+            match data_controller.cached_cities() {
+                Ok(city_data) => {
+                    Self::check_update_error(window_weak.upgrade_in_event_loop(move |window| {
+                        WeatherDisplayController::update_displayed_cities(&window, city_data);
+                    }));
+                }
+                Err(e) => {
+                    log::warn!("Failed to display cities: {}.", e);
+                }
+            }
+        });
+    }
+
     pub fn refresh(&self, window: &AppWindow) {
         Self::set_busy(window);
 
         let window_weak = window.as_weak();
-        Self::refresh_cities(&window_weak, &self.data_controller);
+        Self::refresh_cities(&window_weak, &self.data_controller, &self.refresh_scheduler);
+    }
+
+    // Fetches only the cities with outdated data, without blocking the UI.
+    // Does nothing while the cache is fresh or a retry after a failed refresh is not due yet.
+    pub fn refresh_outdated(&self, window: &AppWindow) {
+        if !self.refresh_scheduler.lock().unwrap().try_start() {
+            return;
+        }
+        self.fetch_outdated(window);
+    }
+
+    // The refresh has to be marked as started in the scheduler.
+    fn fetch_outdated(&self, window: &AppWindow) {
+        let cache_ttl = self.refresh_scheduler.lock().unwrap().cache_ttl();
+
+        let window_weak = window.as_weak();
+        let data_controller = Self::current_controller(&self.data_controller);
+        let refresh_scheduler = self.refresh_scheduler.clone();
+
+        spawn_task(async move {
+            let city_data = match data_controller.refresh_outdated_cities(cache_ttl).await {
+                Ok(city_data) => Some(city_data),
+                Err(e) => {
+                    log::warn!("Failed to update outdated cities: {}.", e);
+                    None
+                }
+            };
+            let joined = refresh_scheduler
+                .lock()
+                .unwrap()
+                .finish(city_data.as_deref());
+
+            // keep the cache up to date in case the application is killed
+            Self::save_cities(&data_controller);
+
+            Self::check_update_error(window_weak.upgrade_in_event_loop(move |window| {
+                if let Some(city_data) = city_data {
+                    WeatherDisplayController::update_displayed_cities(&window, city_data);
+                }
+                Self::unset_joined_busy(&window, joined);
+            }));
+        });
     }
 
+    // The cached data is displayed as is while it is fresh.
     pub fn load(&self, window: &AppWindow) {
         Self::set_busy(window);
 
         let window_weak = window.as_weak();
-        let data_controller = self.data_controller.clone();
+        let data_controller = Self::current_controller(&self.data_controller);
+        let refresh_scheduler = self.refresh_scheduler.clone();
+        let cache_ttl = {
+            let mut refresh_scheduler = refresh_scheduler.lock().unwrap();
+            refresh_scheduler.start();
+            refresh_scheduler.cache_ttl()
+        };
 
         spawn_task(async move {
-            let city_data_res = async {
-                let mut data_controller = data_controller.lock().unwrap();
-                data_controller.load()?;
-                data_controller.refresh_cities()
-            }
-            .await;
+            let mut recovered = false;
+            let city_data_res = match data_controller.load() {
+                Ok(load_status) => {
+                    recovered = load_status == LoadStatus::Recovered;
+                    data_controller.refresh_outdated_cities(cache_ttl).await
+                }
+                Err(e) => Err(e),
+            };
+            let joined = refresh_scheduler
+                .lock()
+                .unwrap()
+                .finish(city_data_res.as_deref().ok());
 
             let city_data = match city_data_res {
-                Ok(city_data) => Some(city_data),
+                Ok(city_data) => city_data,
                 Err(e) => {
                     log::warn!("Failed to load cities: {}.", e);
-                    None
+                    vec![]
                 }
             };
 
             Self::check_update_error(window_weak.upgrade_in_event_loop(move |window| {
-                if let Some(city_data) = city_data {
-                    WeatherDisplayController::update_displayed_cities(&window, city_data);
+                WeatherDisplayController::update_displayed_cities(&window, city_data);
+                if recovered {
+                    window.global::<CityWeather>().set_data_recovered(true);
                 }
                 Self::unset_busy(&window);
+                Self::unset_joined_busy(&window, joined);
             }));
         });
     }
@@ -228,26 +563,37 @@ impl WeatherDisplayController {
     fn refresh_cities(
         window_weak: &Weak<AppWindow>,
         data_controller: &WeatherControllerSharedPointer,
+        refresh_scheduler: &Arc<Mutex<RefreshScheduler>>,
     ) {
+        // the running refresh shows its cities and ends the busy state of this one
+        if !refresh_scheduler.lock().unwrap().try_start_forced() {
+            log::debug!("Refresh already running, waiting for it");
+            return;
+        }
+
         let window_weak = window_weak.clone();
-        let data_controller = data_controller.clone();
+        let data_controller = Self::current_controller(data_controller);
+        let refresh_scheduler = refresh_scheduler.clone();
 
         spawn_task(async move {
-            let city_data_res = async { data_controller.lock().unwrap().refresh_cities() }.await;
-
-            let city_data = match city_data_res {
+            let city_data = match data_controller.refresh_cities().await {
                 Ok(city_data) => Some(city_data),
                 Err(e) => {
                     log::warn!("Failed to update cities: {}.", e);
                     None
                 }
             };
+            let joined = refresh_scheduler
+                .lock()
+                .unwrap()
+                .finish(city_data.as_deref());
 
             Self::check_update_error(window_weak.upgrade_in_event_loop(move |window| {
                 if let Some(city_data) = city_data {
                     WeatherDisplayController::update_displayed_cities(&window, city_data);
                 }
                 Self::unset_busy(&window);
+                Self::unset_joined_busy(&window, joined);
             }));
         });
     }
@@ -261,31 +607,41 @@ impl WeatherDisplayController {
             lat: location.lat as f64,
             lon: location.lon as f64,
             city_name: String::from(&location.name),
+            label: None,
+            note: None,
         };
-        let city_data_res = data_controller.lock().unwrap().add_city(city);
 
-        // update ui
-        let window = window_weak.upgrade().unwrap();
-        match city_data_res {
-            Ok(city_data) => {
+        let window_weak = window_weak.clone();
+        let data_controller = Self::current_controller(data_controller);
+
+        spawn_task(async move {
+            let city_data = match data_controller.add_city(city).await {
+                Ok(city_data) => city_data,
+                Err(e) => {
+                    log::warn!("Failed to add city: {}.", e);
+                    None
+                }
+            };
+            if city_data.is_some() {
+                Self::save_cities(&data_controller);
+            }
+
+            Self::check_update_error(window_weak.upgrade_in_event_loop(move |window| {
                 if let Some(city_data) = city_data {
                     let city_weather = window.global::<CityWeather>();
                     let city_weather_list = city_weather.get_city_weather();
 
-                    let city_weather = Self::city_weather_info_from_data(&city_data);
+                    let units = Self::units_from_ui(&window);
+                    let city_weather = Self::city_weather_info_from_data(&city_data, &units);
                     city_weather_list
                         .as_any()
                         .downcast_ref::<slint::VecModel<CityWeatherInfo>>()
                         .unwrap()
                         .push(city_weather);
                 }
-            }
-            Err(e) => {
-                log::warn!("Failed to add city: {}.", e);
-            }
-        }
-
-        Self::unset_busy(&window);
+                Self::unset_busy(&window);
+            }));
+        });
     }
 
     fn reorder_cities(
@@ -293,47 +649,46 @@ impl WeatherDisplayController {
         data_controller: &WeatherControllerSharedPointer,
         index: i32,
         new_index: i32,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let pos: usize = index.try_into()?;
         let new_pos: usize = new_index.try_into()?;
 
-        data_controller
-            .lock()
-            .unwrap()
-            .reorder_cities(pos, new_pos)?;
+        let data_controller = Self::current_controller(data_controller);
+        data_controller.reorder_cities(pos, new_pos)?;
+        Self::save_cities(&data_controller);
 
         // update ui
         let window = window_weak.upgrade().unwrap();
         let city_weather = window.global::<CityWeather>();
         let city_weather_list = city_weather.get_city_weather();
 
-        let pos_data = city_weather_list
-            .row_data(pos)
-            .ok_or(Box::new(std::io::Error::new(
+        let model = city_weather_list
+            .as_any()
+            .downcast_ref::<slint::VecModel<CityWeatherInfo>>()
+            .expect("CityWeatherInfo model is not provided!");
+
+        if pos >= model.row_count() || new_pos >= model.row_count() {
+            return Err(Box::new(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "Index out of bounds",
-            )))?;
-        let new_pos_data =
-            city_weather_list
-                .row_data(new_pos)
-                .ok_or(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::InvalidInput,
-                    "Index out of bounds",
-                )))?;
-
-        city_weather_list.set_row_data(pos, new_pos_data);
-        city_weather_list.set_row_data(new_pos, pos_data);
+            )));
+        }
+        let pos_data = model.remove(pos);
+        model.insert(new_pos, pos_data);
         Ok(())
     }
 
+    // Returns the name of the removed city.
     fn remove_city(
         window_weak: &Weak<AppWindow>,
         data_controller: &WeatherControllerSharedPointer,
         index: i32,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<SharedString, Box<dyn std::error::Error + Send + Sync>> {
         let pos: usize = index.try_into()?;
 
-        data_controller.lock().unwrap().remove_city(pos)?;
+        let data_controller = Self::current_controller(data_controller);
+        data_controller.remove_city(pos)?;
+        Self::save_cities(&data_controller);
 
         // update ui
         let window = window_weak.upgrade().unwrap();
@@ -345,32 +700,202 @@ impl WeatherDisplayController {
             .downcast_ref::<slint::VecModel<CityWeatherInfo>>()
             .expect("CityWeatherInfo model is not provided!");
 
+        let city_name = model
+            .row_data(pos)
+            .map(|city| match city.label.is_empty() {
+                true => city.city_name,
+                false => city.label,
+            })
+            .unwrap_or_default();
         model.remove(pos);
+        Ok(city_name)
+    }
+
+    fn undo_remove(
+        window_weak: &Weak<AppWindow>,
+        data_controller: &WeatherControllerSharedPointer,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let data_controller = Self::current_controller(data_controller);
+        let restored = data_controller.undo_remove()?;
+        if !restored.is_empty() {
+            Self::save_cities(&data_controller);
+        }
+
+        // update ui
+        let window = window_weak.upgrade().unwrap();
+        let city_weather = window.global::<CityWeather>();
+        city_weather.set_can_undo_delete(false);
+
+        let city_weather_list = city_weather.get_city_weather();
+        let model = city_weather_list
+            .as_any()
+            .downcast_ref::<slint::VecModel<CityWeatherInfo>>()
+            .expect("CityWeatherInfo model is not provided!");
+
+        let cities = data_controller.cached_cities()?;
+        let units = Self::units_from_ui(&window);
+        for pos in restored {
+            if let Some(city) = cities.get(pos) {
+                model.insert(pos, Self::city_weather_info_from_data(city, &units));
+            }
+        }
+        Ok(())
+    }
+
+    fn annotate_city(
+        window_weak: &Weak<AppWindow>,
+        data_controller: &WeatherControllerSharedPointer,
+        index: i32,
+        label: SharedString,
+        note: SharedString,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let pos: usize = index.try_into()?;
+
+        let data_controller = Self::current_controller(data_controller);
+        data_controller.annotate_city(pos, Some(label.into()), Some(note.into()))?;
+        Self::save_cities(&data_controller);
+
+        // update ui, the controller cleans up the values
+        let cities = data_controller.cached_cities()?;
+        let city_data = &cities.get(pos).ok_or(index_out_of_bounds())?.city_data;
+
+        let window = window_weak.upgrade().unwrap();
+        let city_weather_list = window.global::<CityWeather>().get_city_weather();
+        if let Some(mut city) = city_weather_list.row_data(pos) {
+            city.label = city_data.label.as_deref().unwrap_or_default().into();
+            city.note = city_data.note.as_deref().unwrap_or_default().into();
+            city_weather_list.set_row_data(pos, city);
+        }
         Ok(())
     }
 
+    fn export_forecast(
+        window_weak: &Weak<AppWindow>,
+        data_controller: &WeatherControllerSharedPointer,
+        index: i32,
+        format: ExportFormat,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let pos: usize = index.try_into()?;
+
+        let cities = Self::current_controller(data_controller).cached_cities()?;
+        let city = cities.get(pos).ok_or(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Index out of bounds",
+        )))?;
+
+        let format = match format {
+            ExportFormat::Csv => export::ExportFormat::Csv,
+            ExportFormat::Json => export::ExportFormat::Json,
+            ExportFormat::Calendar => export::ExportFormat::Calendar,
+        };
+        let window = window_weak.upgrade().unwrap();
+        export::save_export(city, format, &Self::units_from_ui(&window))
+    }
+
+    // The file is read by the platform, the browser asks the user for it first.
+    fn import_city_list(&self, window: &AppWindow, replace: bool) {
+        let mode = match replace {
+            true => citylist::ImportMode::Replace,
+            false => citylist::ImportMode::Merge,
+        };
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            let window_weak = window.as_weak();
+            let display_controller = self.clone();
+            let opened = export::open_file("application/json,.json", move |data| {
+                if let Some(window) = window_weak.upgrade() {
+                    display_controller.finish_import(&window, data, mode);
+                }
+            });
+            if let Err(e) = opened {
+                self.finish_import(window, Err(e), mode);
+            }
+        }
+
+        #[cfg(target_os = "android")]
+        self.finish_import(window, citylist::read_saved_city_list(), mode);
+
+        #[cfg(all(not(target_arch = "wasm32"), not(target_os = "android")))]
+        {
+            let path = window.global::<CityListSettings>().get_import_path();
+            let data = std::fs::read_to_string(path.as_str()).map_err(Into::into);
+            self.finish_import(window, data, mode);
+        }
+    }
+
+    fn finish_import(
+        &self,
+        window: &AppWindow,
+        data: Result<String, Box<dyn std::error::Error + Send + Sync>>,
+        mode: citylist::ImportMode,
+    ) {
+        let result = match data.and_then(|data| self.apply_import(window, &data, mode)) {
+            Ok(summary) => CityListImport {
+                imported: true,
+                added: summary.added as i32,
+                duplicates: summary.duplicates as i32,
+                removed: summary.removed as i32,
+            },
+            Err(e) => {
+                log::warn!("Failed to import the city list: {}", e);
+                CityListImport::default()
+            }
+        };
+
+        let city_list_settings = window.global::<CityListSettings>();
+        city_list_settings.set_import_result(result);
+        city_list_settings.set_import_finished(true);
+    }
+
+    // The imported cities are saved right away, the new ones are fetched in the background.
+    fn apply_import(
+        &self,
+        window: &AppWindow,
+        data: &str,
+        mode: citylist::ImportMode,
+    ) -> Result<citylist::ImportSummary, Box<dyn std::error::Error + Send + Sync>> {
+        let data_controller = Self::current_controller(&self.data_controller);
+        let summary = citylist::import_city_list(&data_controller, data, mode)?;
+        Self::save_cities(&data_controller);
+
+        Self::update_displayed_cities(window, data_controller.cached_cities()?);
+        self.refresh_scheduler.lock().unwrap().start();
+        self.fetch_outdated(window);
+        Ok(summary)
+    }
+
     fn search_location(
         window_weak: &Weak<AppWindow>,
         data_controller: &WeatherControllerSharedPointer,
         query: slint::SharedString,
+        search_generation: &Arc<AtomicU64>,
+        generation: u64,
     ) {
         let window_weak = window_weak.clone();
-        let data_controller = data_controller.clone();
+        let data_controller = Self::current_controller(data_controller);
         let query = query.to_string();
+        let search_generation = search_generation.clone();
 
         spawn_task(async move {
-            let locations_res =
-                async { data_controller.lock().unwrap().search_location(query) }.await;
-
-            let locations = match locations_res {
-                Ok(locations) => Some(locations),
-                Err(e) => {
-                    log::warn!("Failed to search for location: {}.", e);
-                    None
+            let locations = match parse_coordinates(&query) {
+                Some((lat, lon)) => {
+                    Some(vec![locate_coordinates(&data_controller, lat, lon).await])
                 }
+                None => match data_controller.search_location(query).await {
+                    Ok(locations) => Some(locations),
+                    Err(e) => {
+                        log::warn!("Failed to search for location: {}.", e);
+                        None
+                    }
+                },
             };
 
             Self::check_update_error(window_weak.upgrade_in_event_loop(move |window| {
+                // the results of an older query do not replace the newer ones
+                if search_generation.load(Ordering::SeqCst) != generation {
+                    return;
+                }
                 if let Some(locations) = locations {
                     WeatherDisplayController::update_location_search_results(&window, locations);
                 }
@@ -378,9 +903,39 @@ impl WeatherDisplayController {
         });
     }
 
+    // The gazetteer is searched on each typed letter, there is no request to wait for.
+    fn search_location_offline(window_weak: &Weak<AppWindow>, query: slint::SharedString) {
+        if let Some(window) = window_weak.upgrade() {
+            let locations = match parse_coordinates(&query) {
+                Some((lat, lon)) => {
+                    vec![location_at(lat, lon, gazetteer::reverse_geocode(lat, lon))]
+                }
+                None => gazetteer::search_location(&query),
+            };
+            WeatherDisplayController::update_location_search_results(&window, locations);
+        }
+    }
+
+    // The pointer is cloned, so a replaced controller does not affect the running tasks.
+    fn current_controller(
+        data_controller: &WeatherControllerSharedPointer,
+    ) -> WeatherControllerPointer {
+        data_controller.read().unwrap().clone()
+    }
+
+    // The edits are saved right away, as the web build has no exit to save the state on.
+    fn save_cities(data_controller: &WeatherControllerPointer) {
+        if let Err(e) = data_controller.save() {
+            log::warn!("Error while saving state: {}", e)
+        }
+    }
+
     fn update_displayed_cities(window: &AppWindow, data: Vec<CityWeatherData>) {
-        let display_vector: Vec<CityWeatherInfo> =
-            data.iter().map(Self::city_weather_info_from_data).collect();
+        let units = Self::units_from_ui(window);
+        let display_vector: Vec<CityWeatherInfo> = data
+            .iter()
+            .map(|city_data| Self::city_weather_info_from_data(city_data, &units))
+            .collect();
 
         let city_weather = window.global::<CityWeather>().get_city_weather();
         let model = city_weather
@@ -414,6 +969,13 @@ impl WeatherDisplayController {
         window.global::<BusyLayerController>().invoke_unset_busy();
     }
 
+    // The forced refreshes that waited for the finished one are done as well.
+    fn unset_joined_busy(window: &AppWindow, joined: usize) {
+        for _ in 0..joined {
+            Self::unset_busy(window);
+        }
+    }
+
     fn check_update_error<E: std::fmt::Display>(result: Result<(), E>) {
         if let Err(e) = result {
             log::error!("Error while updating UI: {}", e);
@@ -435,45 +997,201 @@ impl WeatherDisplayController {
         }
     }
 
-    fn weather_info_from_data(data: &DayWeatherData) -> WeatherInfo {
+    fn weather_info_from_data(
+        data: &DayWeatherData,
+        utc_offset: Option<i32>,
+        units: &Units,
+    ) -> WeatherInfo {
+        let temperature = |value: f64| units.temperature.convert(value) as f32;
+        let precipitation = |value: f64| units.precipitation.convert(value) as f32;
+        let wind_speed = |value: f64| units.wind_speed.convert(value) as f32;
+
         WeatherInfo {
             description: SharedString::from(&data.description),
             icon_type: Self::icon_type_from_condition(&data.condition),
-            current_temp: data.current_temperature as f32,
+            current_temp: temperature(data.current_temperature),
             detailed_temp: TemperatureInfo {
-                min: data.detailed_temperature.min as f32,
-                max: data.detailed_temperature.max as f32,
+                min: temperature(data.detailed_temperature.min),
+                max: temperature(data.detailed_temperature.max),
 
-                morning: data.detailed_temperature.morning as f32,
-                day: data.detailed_temperature.day as f32,
-                evening: data.detailed_temperature.evening as f32,
-                night: data.detailed_temperature.night as f32,
+                morning: temperature(data.detailed_temperature.morning),
+                day: temperature(data.detailed_temperature.day),
+                evening: temperature(data.detailed_temperature.evening),
+                night: temperature(data.detailed_temperature.night),
             },
             uv: data.uv_index as i32,
             precipitation_prob: data.precipitation.probability as f32,
-            rain: data.precipitation.rain_volume as f32,
-            snow: data.precipitation.snow_volume as f32,
+            rain: precipitation(data.precipitation.rain_volume),
+            snow: precipitation(data.precipitation.snow_volume),
+
+            feels_like: temperature(data.feels_like_temperature),
+            humidity: data.humidity as f32,
+            pressure: units.convert_pressure(data.pressure) as f32,
+            dew_point: temperature(data.dew_point),
+            wind_speed: wind_speed(data.wind_speed),
+            wind_gust: data.wind_gust.map_or(-1.0, wind_speed),
+            wind_direction: data.wind_direction as f32,
+            cloud_cover: data.cloud_cover as f32,
+            visibility: data
+                .visibility
+                .map_or(-1.0, |visibility| units.convert_visibility(visibility))
+                as f32,
+            sunrise: SharedString::from(
+                data.sunrise
+                    .map(|sunrise| get_time_from_timestamp(sunrise, utc_offset))
+                    .unwrap_or_default(),
+            ),
+            sunset: SharedString::from(
+                data.sunset
+                    .map(|sunset| get_time_from_timestamp(sunset, utc_offset))
+                    .unwrap_or_default(),
+            ),
         }
     }
 
-    fn forecast_weather_info_from_data(data: &[ForecastWeatherData]) -> Vec<WeatherForecastInfo> {
+    fn forecast_weather_info_from_data(
+        data: &[ForecastWeatherData],
+        utc_offset: Option<i32>,
+        units: &Units,
+    ) -> Vec<WeatherForecastInfo> {
         data.iter()
             .map(|forecast_data| WeatherForecastInfo {
-                day_name: SharedString::from(&forecast_data.day_name),
-                weather_info: Self::weather_info_from_data(&forecast_data.weather_data),
+                day_name: SharedString::from(get_day_name_from_timestamp(
+                    forecast_data.timestamp,
+                    utc_offset,
+                )),
+                weather_info: Self::weather_info_from_data(
+                    &forecast_data.weather_data,
+                    utc_offset,
+                    units,
+                ),
+            })
+            .collect()
+    }
+
+    fn hourly_weather_info_from_data(
+        data: &[HourlyWeatherData],
+        utc_offset: Option<i32>,
+        units: &Units,
+    ) -> Vec<HourlyForecastInfo> {
+        data.iter()
+            .map(|hourly_data| HourlyForecastInfo {
+                time: SharedString::from(get_time_from_timestamp(
+                    hourly_data.timestamp,
+                    utc_offset,
+                )),
+                icon_type: Self::icon_type_from_condition(&hourly_data.condition),
+                temp: units.temperature.convert(hourly_data.temperature) as f32,
+                precipitation_prob: hourly_data.precipitation.probability as f32,
+                rain: units
+                    .precipitation
+                    .convert(hourly_data.precipitation.rain_volume) as f32,
+                snow: units
+                    .precipitation
+                    .convert(hourly_data.precipitation.snow_volume) as f32,
+            })
+            .collect()
+    }
+
+    fn alert_severity_from_data(severity: &WeatherAlertSeverity) -> AlertSeverity {
+        match severity {
+            WeatherAlertSeverity::Minor => AlertSeverity::Minor,
+            WeatherAlertSeverity::Moderate => AlertSeverity::Moderate,
+            WeatherAlertSeverity::Severe => AlertSeverity::Severe,
+        }
+    }
+
+    fn alerts_info_from_data(
+        data: &[WeatherAlert],
+        utc_offset: Option<i32>,
+    ) -> Vec<WeatherAlertInfo> {
+        // most severe alerts first, the first one is used for the tile badge
+        let mut alerts: Vec<&WeatherAlert> = data.iter().collect();
+        alerts.sort_by_key(|alert| std::cmp::Reverse(alert.severity()));
+
+        alerts
+            .iter()
+            .map(|alert| WeatherAlertInfo {
+                sender: SharedString::from(&alert.sender),
+                event: SharedString::from(&alert.event),
+                start: SharedString::from(get_day_time_from_timestamp(alert.start, utc_offset)),
+                end: SharedString::from(get_day_time_from_timestamp(alert.end, utc_offset)),
+                description: SharedString::from(alert.description.trim()),
+                tags: SharedString::from(alert.tags.join(", ")),
+                severity: Self::alert_severity_from_data(&alert.severity()),
             })
             .collect()
     }
 
-    fn city_weather_info_from_data(data: &CityWeatherData) -> CityWeatherInfo {
-        let current_weather_info = Self::weather_info_from_data(&data.weather_data.current_data);
-        let forecast_weather_info =
-            Self::forecast_weather_info_from_data(&data.weather_data.forecast_data);
+    fn nowcast_info_from_data(data: &CityWeatherData, now: i64) -> NowcastInfo {
+        let (kind, minutes) = match get_precipitation_nowcast(&data.weather_data.minutely_data, now)
+        {
+            Some(PrecipitationNowcast::Starting(minutes)) => (NowcastKind::Starting, minutes),
+            Some(PrecipitationNowcast::Stopping(minutes)) => (NowcastKind::Stopping, minutes),
+            Some(PrecipitationNowcast::Continuing) => (NowcastKind::Continuing, 0),
+            None => (NowcastKind::None, 0),
+        };
+
+        NowcastInfo {
+            kind,
+            is_snow: data.weather_data.current_data.condition == WeatherCondition::Snowy,
+            minutes: minutes as i32,
+        }
+    }
+
+    fn refresh_info_from_data(status: &RefreshStatus) -> RefreshInfo {
+        let (kind, error) = match status {
+            RefreshStatus::Fresh => (ui::RefreshStatus::Fresh, ""),
+            RefreshStatus::Stale(error) => (ui::RefreshStatus::Stale, error.as_str()),
+            RefreshStatus::Failed(error) => (ui::RefreshStatus::Failed, error.as_str()),
+        };
+
+        RefreshInfo {
+            status: kind,
+            error: SharedString::from(error),
+        }
+    }
+
+    fn city_weather_info_from_data(data: &CityWeatherData, units: &Units) -> CityWeatherInfo {
+        let utc_offset = data.weather_data.utc_offset;
+        let current_weather_info =
+            Self::weather_info_from_data(&data.weather_data.current_data, utc_offset, units);
+        let forecast_weather_info = Self::forecast_weather_info_from_data(
+            &data.weather_data.forecast_data,
+            utc_offset,
+            units,
+        );
+        let hourly_weather_info =
+            Self::hourly_weather_info_from_data(&data.weather_data.hourly_data, utc_offset, units);
+        // the chart starts at the same minute as the nowcast
+        let now = chrono::Utc::now().timestamp();
+        let nowcast = Self::nowcast_info_from_data(data, now);
+        let minutely_precipitation: Vec<f32> =
+            upcoming_minutely_data(&data.weather_data.minutely_data, now)
+                .iter()
+                .map(|minutely_data| minutely_data.precipitation as f32)
+                .collect();
 
         CityWeatherInfo {
             city_name: SharedString::from(&data.city_data.city_name),
+            label: data.city_data.label.as_deref().unwrap_or_default().into(),
+            note: data.city_data.note.as_deref().unwrap_or_default().into(),
             current_weather: current_weather_info,
             forecast_weather: Rc::new(slint::VecModel::from(forecast_weather_info)).into(),
+            hourly_weather: Rc::new(slint::VecModel::from(hourly_weather_info)).into(),
+            nowcast,
+            minutely_precipitation: Rc::new(slint::VecModel::from(minutely_precipitation)).into(),
+            alerts: Rc::new(slint::VecModel::from(Self::alerts_info_from_data(
+                &data.alerts,
+                utc_offset,
+            )))
+            .into(),
+            refresh: Self::refresh_info_from_data(&data.refresh_status),
+            // older cached data has no fetch time, the observation time is close enough
+            updated_minute: data
+                .fetched_at
+                .or(data.observed_at)
+                .map_or(0, |timestamp| (timestamp / 60) as i32),
         }
     }
 